@@ -28,8 +28,8 @@ Footnote.[^1]
     c.bench_function("render_markdown", |b| b.iter(|| render_html(&doc)));
 }
 
-fn bench_templates_hash(c: &mut Criterion) {
-    c.bench_function("templates_hash_small", |b| {
+fn bench_template_hashes(c: &mut Criterion) {
+    c.bench_function("template_hashes_small", |b| {
         b.iter_batched(
             || {
                 let tmp = tempdir().unwrap();
@@ -40,12 +40,12 @@ fn bench_templates_hash(c: &mut Criterion) {
                 (tmp, dir)
             },
             |(_, dir)| {
-                let _ = ssg::cache::templates_hash(&dir).unwrap();
+                let _ = ssg::cache::template_hashes(std::slice::from_ref(&dir)).unwrap();
             },
             BatchSize::SmallInput,
         )
     });
 }
 
-criterion_group!(benches, bench_render_markdown, bench_templates_hash);
+criterion_group!(benches, bench_render_markdown, bench_template_hashes);
 criterion_main!(benches);
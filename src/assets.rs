@@ -0,0 +1,159 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Serialize;
+use sha2::{Digest, Sha384};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::config::AssetsConfig;
+
+/// Name of the manifest written to the output root.
+pub const MANIFEST_FILE: &str = "asset-manifest.json";
+
+/// Where each static asset ended up, keyed by its path relative to the
+/// output root before fingerprinting (`css/style.css`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct AssetManifest {
+    pub entries: BTreeMap<String, AssetEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AssetEntry {
+    /// Site-relative URL of the written file, fingerprinted if enabled.
+    pub url: String,
+    /// Subresource Integrity value for `integrity="..."` attributes; only
+    /// computed for the configured extensions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+}
+
+impl AssetManifest {
+    /// Lists every asset, hashing those with one of the configured
+    /// extensions and, with `fingerprint` on, moving their destination to a
+    /// content-hashed name.
+    pub fn build(
+        cfg: &AssetsConfig,
+        out_root: &Path,
+        assets: &mut [(PathBuf, PathBuf)],
+    ) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        for (src, dest) in assets.iter_mut() {
+            let tracked = src
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| cfg.extensions.iter().any(|x| x.eq_ignore_ascii_case(e)));
+            let key = rel_url(out_root, dest);
+            let mut hash = None;
+            if tracked {
+                let bytes = fs::read(&*src)?;
+                if cfg.fingerprint {
+                    *dest = fingerprinted(dest, &bytes);
+                }
+                hash = Some(integrity(&bytes));
+            }
+            entries.insert(
+                key,
+                AssetEntry {
+                    url: format!("/{}", rel_url(out_root, dest)),
+                    integrity: hash,
+                },
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn get(&self, path: &str) -> Option<&AssetEntry> {
+        self.entries.get(path.trim_start_matches('/'))
+    }
+
+    /// Writes the manifest as JSON, unless there is nothing in it.
+    pub fn write(&self, out_root: &Path) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        fs::create_dir_all(out_root)?;
+        fs::write(out_root.join(MANIFEST_FILE), json)
+    }
+}
+
+/// `css/style.css` becomes `css/style.<hash>.css`, using the first 10 hex
+/// digits of the content's blake3 hash.
+pub fn fingerprinted(dest: &Path, bytes: &[u8]) -> PathBuf {
+    let hash = blake3::hash(bytes).to_hex();
+    let stem = dest
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match dest.extension() {
+        Some(ext) => format!("{stem}.{}.{}", &hash[..10], ext.to_string_lossy()),
+        None => format!("{stem}.{}", &hash[..10]),
+    };
+    dest.with_file_name(name)
+}
+
+fn rel_url(out_root: &Path, path: &Path) -> String {
+    path.strip_prefix(out_root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// `sha384-<base64>`, the strongest hash all browsers accept for SRI.
+pub fn integrity(bytes: &[u8]) -> String {
+    format!("sha384-{}", STANDARD.encode(Sha384::digest(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn fingerprints_tracked_extensions_only() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let out = tmp.path().join("out");
+        fs::create_dir_all(src.join("css")).unwrap();
+        fs::write(src.join("css/style.css"), "body{}").unwrap();
+        fs::write(src.join("logo.png"), "png").unwrap();
+
+        let mut assets = vec![
+            (src.join("css/style.css"), out.join("css/style.css")),
+            (src.join("logo.png"), out.join("logo.png")),
+        ];
+        let cfg = AssetsConfig {
+            fingerprint: true,
+            ..Default::default()
+        };
+        let manifest = AssetManifest::build(&cfg, &out, &mut assets).unwrap();
+
+        let entry = manifest.get("/css/style.css").unwrap();
+        let hashed = fingerprinted(Path::new("css/style.css"), b"body{}");
+        assert_eq!(entry.url, format!("/{}", hashed.display()));
+        assert_eq!(assets[0].1, out.join(&hashed));
+        assert_eq!(assets[1].1, out.join("logo.png"));
+        assert_eq!(manifest.get("logo.png").unwrap().integrity, None);
+        // Known vector: SHA-384 of the empty string.
+        assert_eq!(
+            integrity(b""),
+            "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb"
+        );
+    }
+
+    #[test]
+    fn without_fingerprinting_paths_stay_put() {
+        let tmp = tempdir().unwrap();
+        let js = tmp.path().join("app.js");
+        fs::write(&js, "1").unwrap();
+        let out = tmp.path().join("out");
+        let mut assets = vec![(js, out.join("app.js"))];
+
+        let manifest = AssetManifest::build(&AssetsConfig::default(), &out, &mut assets).unwrap();
+        assert_eq!(manifest.get("app.js").unwrap().url, "/app.js");
+        assert_eq!(assets[0].1, out.join("app.js"));
+    }
+}
@@ -0,0 +1,120 @@
+//! Page bundles: a directory whose only Markdown file is an `index.md` is a
+//! page bundle, and every other file in it belongs to that page.
+//!
+//! Bundle files are published next to the page's output, wherever its URL
+//! puts it, so links relative to the page keep working. Templates see them
+//! as `page.assets`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::routing::url_for_out_path;
+
+/// A page bundle, keyed by the path of its `index.md` in [`bundles`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// Source directory.
+    pub dir: PathBuf,
+    /// Directory of the page's output, where the bundle's files go.
+    pub out_dir: PathBuf,
+}
+
+impl Bundle {
+    /// Site-relative URL of `out_dir`, with a trailing slash.
+    pub fn url(&self, out_root: &Path) -> String {
+        url_for_out_path(out_root, &self.out_dir.join("index.html"))
+    }
+}
+
+/// The bundles among `pages`, given as `(source, output path)` pairs. The
+/// content root is never a bundle, even with an `index.md` of its own.
+pub fn bundles(src_root: &Path, pages: &[(&Path, &Path)]) -> HashMap<PathBuf, Bundle> {
+    pages
+        .iter()
+        .filter(|(md, _)| md.file_name().is_some_and(|n| n == "index.md"))
+        .filter_map(|&(md, out_path)| {
+            let dir = md.parent().filter(|d| *d != src_root)?;
+            let alone = pages
+                .iter()
+                .all(|(other, _)| *other == md || !other.starts_with(dir));
+            alone.then(|| {
+                let bundle = Bundle {
+                    dir: dir.to_path_buf(),
+                    out_dir: out_path.parent().unwrap_or(out_path).to_path_buf(),
+                };
+                (md.to_path_buf(), bundle)
+            })
+        })
+        .collect()
+}
+
+/// Moves every asset inside a bundle next to its page's output. Returns the
+/// new destinations, sorted, for every bundle.
+pub fn relocate(
+    assets: &mut [(PathBuf, PathBuf)],
+    bundles: &HashMap<PathBuf, Bundle>,
+) -> HashMap<PathBuf, Vec<PathBuf>> {
+    let mut moved: HashMap<PathBuf, Vec<PathBuf>> =
+        bundles.keys().map(|md| (md.clone(), Vec::new())).collect();
+    for (src, dest) in assets.iter_mut() {
+        let found = bundles
+            .iter()
+            .find_map(|(md, b)| Some((md, b, src.strip_prefix(&b.dir).ok()?)));
+        if let Some((md, bundle, rel)) = found {
+            *dest = bundle.out_dir.join(rel);
+            moved.entry(md.clone()).or_default().push(dest.clone());
+        }
+    }
+    moved.values_mut().for_each(|dests| dests.sort());
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_lone_index_files_make_bundles() {
+        let src = Path::new("/s");
+        let pages = [
+            (Path::new("/s/index.md"), Path::new("/o/index.html")),
+            (
+                Path::new("/s/posts/trip/index.md"),
+                Path::new("/o/2024/trip/index.html"),
+            ),
+            (
+                Path::new("/s/posts/index.md"),
+                Path::new("/o/posts/index.html"),
+            ),
+            (
+                Path::new("/s/posts/a.md"),
+                Path::new("/o/posts/a/index.html"),
+            ),
+        ];
+        let found = bundles(src, &pages);
+        assert_eq!(found.len(), 1);
+        let trip = &found[Path::new("/s/posts/trip/index.md")];
+        assert_eq!(trip.dir, Path::new("/s/posts/trip"));
+        assert_eq!(trip.url(Path::new("/o")), "/2024/trip/");
+
+        let mut assets = vec![
+            (
+                PathBuf::from("/s/posts/trip/img/a.jpg"),
+                PathBuf::from("/o/posts/trip/img/a.jpg"),
+            ),
+            (
+                PathBuf::from("/s/posts/b.jpg"),
+                PathBuf::from("/o/posts/b.jpg"),
+            ),
+        ];
+        let moved = relocate(&mut assets, &found);
+        assert_eq!(assets[0].1, Path::new("/o/2024/trip/img/a.jpg"));
+        assert_eq!(
+            moved[Path::new("/s/posts/trip/index.md")],
+            [assets[0].1.clone()]
+        );
+        assert_eq!(assets[1].1, Path::new("/o/posts/b.jpg"));
+    }
+}
@@ -1,23 +1,131 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fs, io,
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
+use crate::{config::SiteConfig, content::PageMeta, context::Environment};
+
+/// Bumped whenever the cache layout or the meaning of its entries changes.
+pub const FORMAT_VERSION: u32 = 10;
+
+/// Version of the binary that wrote the cache; output may differ between releases.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct BuildCache {
-    pub templates_hash: String,
-    pub pages: HashMap<String, String>,
+    pub format: u32,
+    pub tool_version: String,
+    pub config_hash: String,
+    /// Content hash of every template, keyed by template name.
+    pub templates: HashMap<String, String>,
+    pub pages: HashMap<String, PageEntry>,
+    /// Outputs of the asset stage, keyed by path relative to the output root.
+    pub assets: HashMap<String, BuiltAsset>,
+    /// Static files placed in the output, keyed by path relative to the
+    /// output root.
+    pub copied: HashMap<String, CopiedAsset>,
+    /// Processed images each rendered output uses, keyed by the output's
+    /// path relative to the output root.
+    pub images: HashMap<String, BTreeSet<String>>,
+    /// Term, index, redirect and feed files the taxonomies produced, relative
+    /// to the output root.
+    pub taxonomy_outputs: BTreeSet<String>,
+    /// Section listing pages after the first, and their redirects, relative
+    /// to the output root.
+    pub section_outputs: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PageEntry {
+    pub hash: String,
+    /// Templates the page rendered with, including inherited and included ones.
+    pub templates: BTreeSet<String>,
+    /// Rendered file, relative to the output root.
+    pub output: PathBuf,
+    /// Parsed front matter, reused while the source hash is unchanged.
+    pub meta: PageMeta,
+    /// URLs of the page's bundle files, as listed in `page.assets`.
+    pub assets: Vec<String>,
 }
 
-pub fn load(out_root: &Path) -> BuildCache {
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuiltAsset {
+    /// Hash of the inputs and settings; also names the staged file.
+    pub key: String,
+    /// Source paths relative to the site root.
+    pub inputs: Vec<String>,
+    /// Whether a source map was written alongside.
+    pub map: bool,
+}
+
+/// The source a static output file was copied or linked from, as it was then.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CopiedAsset {
+    pub source: PathBuf,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime: u64,
+    pub hash: String,
+}
+
+impl BuildCache {
+    pub fn new(config_hash: String, templates: HashMap<String, String>) -> Self {
+        Self {
+            format: FORMAT_VERSION,
+            tool_version: TOOL_VERSION.to_string(),
+            config_hash,
+            templates,
+            pages: HashMap::new(),
+            assets: HashMap::new(),
+            copied: HashMap::new(),
+            images: HashMap::new(),
+            taxonomy_outputs: BTreeSet::new(),
+            section_outputs: BTreeSet::new(),
+        }
+    }
+
+    /// Whether a page can be skipped: its source and every template it used
+    /// hash the same as when it was last rendered, and the output is still there.
+    pub fn is_fresh(
+        &self,
+        rel: &str,
+        file_hash: &str,
+        templates: &HashMap<String, String>,
+        out_root: &Path,
+    ) -> bool {
+        self.pages.get(rel).is_some_and(|entry| {
+            entry.hash == file_hash
+                && entry
+                    .templates
+                    .iter()
+                    .all(|t| self.templates.get(t) == templates.get(t))
+                && out_root.join(&entry.output).is_file()
+        })
+    }
+}
+
+/// Loads the cache written by the previous build, discarding it when it was
+/// written by another ssg version, in another format, or for another config.
+pub fn load(out_root: &Path, config_hash: &str) -> BuildCache {
     let p = out_root.join(".ssg-cache.json");
-    match fs::read(&p) {
+    let cache: BuildCache = match fs::read(&p) {
         Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
         Err(_) => BuildCache::default(),
+    };
+    if cache.format != FORMAT_VERSION
+        || cache.tool_version != TOOL_VERSION
+        || cache.config_hash != config_hash
+    {
+        return BuildCache::default();
     }
+    cache
 }
 
 pub fn save(out_root: &Path, cache: &BuildCache) -> io::Result<()> {
@@ -31,30 +139,43 @@ pub fn save(out_root: &Path, cache: &BuildCache) -> io::Result<()> {
 
 pub fn file_hash(path: &Path) -> io::Result<String> {
     let data = fs::read(path)?;
-    Ok(blake3::hash(&data).to_hex().to_string())
+    Ok(hash_bytes(&data))
 }
 
-pub fn templates_hash(dir: &Path) -> io::Result<String> {
-    let mut files: Vec<PathBuf> = Vec::new();
-    for e in WalkDir::new(dir) {
-        let e = match e {
-            Ok(x) => x,
-            Err(_) => continue,
-        };
-        if e.file_type().is_file() {
-            files.push(e.into_path());
-        }
-    }
-    files.sort();
+pub fn hash_bytes(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
 
-    let mut hasher = blake3::Hasher::new();
-    for p in files {
-        hasher.update(p.to_string_lossy().as_bytes());
-        if let Ok(data) = fs::read(&p) {
-            hasher.update(&data);
+/// Hashes of the templates under `dirs`, keyed by name; a later directory
+/// overrides templates of the same name, as in `Templates::load_layers`.
+pub fn template_hashes(dirs: &[PathBuf]) -> io::Result<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    for dir in dirs {
+        for e in WalkDir::new(dir) {
+            let e = match e {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if !e.file_type().is_file() {
+                continue;
+            }
+            let name = e
+                .path()
+                .strip_prefix(dir)
+                .unwrap_or(e.path())
+                .to_string_lossy()
+                .replace('\\', "/");
+            hashes.insert(name, file_hash(e.path())?);
         }
     }
-    Ok(hasher.finalize().to_hex().to_string())
+    Ok(hashes)
+}
+
+/// Fingerprint of the settings that feed into every rendered page,
+/// including the environment templates see as `build.environment`.
+pub fn config_hash(cfg: &SiteConfig, env: Environment) -> String {
+    let bytes = serde_json::to_vec(&(cfg, env)).unwrap_or_default();
+    blake3::hash(&bytes).to_hex().to_string()
 }
 
 #[cfg(test)]
@@ -79,19 +200,85 @@ mod tests {
     }
 
     #[test]
-    fn templates_hash_changes_on_update() {
+    fn config_hash_depends_on_environment() {
+        let cfg = SiteConfig::default();
+        assert_ne!(
+            config_hash(&cfg, Environment::Production),
+            config_hash(&cfg, Environment::Development)
+        );
+    }
+
+    #[test]
+    fn template_hashes_are_keyed_by_name() {
         let dir = tempdir().unwrap();
         let tpls = dir.path().join("templates");
-        fs::create_dir(&tpls).unwrap();
+        fs::create_dir_all(tpls.join("partials")).unwrap();
         let base = tpls.join("base.html");
         fs::write(&base, "<title>{{ page.title }}</title>").unwrap();
+        fs::write(tpls.join("partials/nav.html"), "<nav></nav>").unwrap();
 
-        let h1 = templates_hash(&tpls).unwrap();
+        let h1 = template_hashes(std::slice::from_ref(&tpls)).unwrap();
+        assert!(h1.contains_key("base.html"));
+        assert!(h1.contains_key("partials/nav.html"));
 
         thread::sleep(Duration::from_millis(10));
         fs::write(&base, "<title>{{ page.title }} X</title>").unwrap();
-        let h2 = templates_hash(&tpls).unwrap();
+        let h2 = template_hashes(std::slice::from_ref(&tpls)).unwrap();
 
-        assert_ne!(h1, h2);
+        assert_ne!(h1["base.html"], h2["base.html"]);
+        assert_eq!(h1["partials/nav.html"], h2["partials/nav.html"]);
+    }
+
+    #[test]
+    fn page_is_stale_only_when_its_own_templates_change() {
+        let out = tempdir().unwrap();
+        fs::write(out.path().join("a.html"), "a").unwrap();
+
+        let mut cache = BuildCache::new("cfg".into(), HashMap::new());
+        cache.templates.insert("post.html".into(), "p1".into());
+        cache.templates.insert("tag.html".into(), "t1".into());
+        cache.pages.insert(
+            "a.md".into(),
+            PageEntry {
+                hash: "a1".into(),
+                templates: ["post.html".to_string()].into(),
+                output: "a.html".into(),
+                ..Default::default()
+            },
+        );
+
+        let mut now = cache.templates.clone();
+        now.insert("tag.html".into(), "t2".into());
+        assert!(cache.is_fresh("a.md", "a1", &now, out.path()));
+        assert!(!cache.is_fresh("a.md", "a2", &now, out.path()));
+
+        fs::remove_file(out.path().join("a.html")).unwrap();
+        assert!(!cache.is_fresh("a.md", "a1", &now, out.path()));
+        fs::write(out.path().join("a.html"), "a").unwrap();
+
+        now.insert("post.html".into(), "p2".into());
+        assert!(!cache.is_fresh("a.md", "a1", &now, out.path()));
+    }
+
+    #[test]
+    fn load_discards_cache_from_other_config_or_version() {
+        let out = tempdir().unwrap();
+        let mut cache = BuildCache::new("cfg".into(), HashMap::new());
+        cache.pages.insert("a.md".into(), PageEntry::default());
+        save(out.path(), &cache).unwrap();
+
+        assert_eq!(load(out.path(), "cfg").pages.len(), 1);
+        assert!(load(out.path(), "other").pages.is_empty());
+
+        cache.tool_version = "0.0.0-old".into();
+        save(out.path(), &cache).unwrap();
+        assert!(load(out.path(), "cfg").pages.is_empty());
+
+        fs::write(
+            out.path().join(".ssg-cache.json"),
+            br#"{"templates_hash":"x","pages":{"a.md":"h"}}"#,
+        )
+        .unwrap();
+        assert!(load(out.path(), "cfg").pages.is_empty());
     }
 }
@@ -1,5 +1,7 @@
+use crate::routing::SlugStyle;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs, io,
     path::{Path, PathBuf},
 };
@@ -13,6 +15,27 @@ pub struct SiteConfig {
     pub description: Option<String>,
     pub author: Option<String>,
 
+    /// URL patterns keyed by content directory, e.g. `posts = "/:year/:month/:slug/"`.
+    pub permalinks: BTreeMap<String, String>,
+    /// Write pages as `slug.html` instead of `slug/index.html`.
+    pub ugly_urls: bool,
+    pub redirects: RedirectsConfig,
+    /// Slug strategy for pages, tags and heading anchors.
+    pub slugify: SlugStyle,
+    /// Front matter keys that group pages into term pages. Defaults to `tags`.
+    pub taxonomies: Vec<TaxonomyConfig>,
+    /// Defaults for every paginated listing.
+    pub pagination: PaginationConfig,
+    pub assets: AssetsConfig,
+    pub images: ImagesConfig,
+    /// Minify every HTML file the build writes.
+    pub minify_html: bool,
+    /// Extra gitignore-style patterns for paths the build skips, on top of
+    /// `.ssgignore` and hidden files.
+    pub ignore: Vec<String>,
+    /// Free-form settings for templates, on top of the theme's defaults.
+    pub extra: BTreeMap<String, serde_json::Value>,
+
     #[serde(rename = "src")]
     pub src_dir: PathBuf,
     #[serde(rename = "out")]
@@ -27,24 +50,300 @@ impl Default for SiteConfig {
             theme: "default".to_string(),
             description: None,
             author: None,
+            permalinks: BTreeMap::new(),
+            ugly_urls: false,
+            redirects: RedirectsConfig::default(),
+            slugify: SlugStyle::default(),
+            taxonomies: vec![TaxonomyConfig::tags()],
+            pagination: PaginationConfig::default(),
+            assets: AssetsConfig::default(),
+            images: ImagesConfig::default(),
+            minify_html: false,
+            ignore: Vec::new(),
+            extra: BTreeMap::new(),
             src_dir: PathBuf::from("src"),
             out_dir: PathBuf::from("out"),
         }
     }
 }
 
+/// Server-side redirect files generated next to the alias pages.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RedirectsConfig {
+    /// Write a Netlify-style `_redirects` file.
+    pub netlify: bool,
+    /// Write `redirects.nginx.conf` with an nginx `map` block.
+    pub nginx: bool,
+}
+
+/// The `[assets]` table.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AssetsConfig {
+    /// Write assets with these extensions under content-hashed names.
+    pub fingerprint: bool,
+    /// Extensions that get an integrity hash and, if enabled, a fingerprint.
+    pub extensions: Vec<String>,
+    /// Minify CSS (including compiled SCSS) and JavaScript.
+    pub minify: bool,
+    /// Write a `.map` next to every minified file.
+    pub source_maps: bool,
+    /// Files concatenated into one output, in order.
+    pub bundles: Vec<BundleConfig>,
+    /// Hard-link static files into the output instead of copying them when
+    /// both sit on one filesystem. Off by default: anything that edits the
+    /// output in place would edit the sources too.
+    pub hardlink: bool,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        Self {
+            fingerprint: false,
+            extensions: vec!["css".to_string(), "js".to_string()],
+            minify: false,
+            source_maps: true,
+            bundles: Vec::new(),
+            hardlink: false,
+        }
+    }
+}
+
+/// One `[[assets.bundles]]` entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct BundleConfig {
+    /// Output path relative to the output root, e.g. `js/site.js`.
+    pub output: String,
+    /// Source paths relative to the site root; `.scss` inputs are compiled.
+    pub inputs: Vec<String>,
+}
+
+/// The `[images]` table, used by the image functions in templates.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Widths `image_srcset` generates; wider than the source are skipped.
+    pub widths: Vec<u32>,
+    /// JPEG and AVIF quality, 1 to 100. WebP output is lossless.
+    pub quality: u8,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            widths: vec![480, 960, 1440],
+            quality: 80,
+        }
+    }
+}
+
+/// The `[pagination]` table: defaults for term pages and section pages,
+/// which taxonomies and `_index.md` front matter can override.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PaginationConfig {
+    /// Items per page; 0 puts everything on one page.
+    pub per_page: usize,
+    /// URL segment before the page number, as in `/tags/rust/page/2/`.
+    pub path: String,
+    /// Page numbers listed on each side of the current one before eliding.
+    pub window: usize,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            per_page: 10,
+            path: "page".to_string(),
+            window: 2,
+        }
+    }
+}
+
+/// A `[[taxonomies]]` entry: pages list terms under the front matter key
+/// `name` and get one listing page per term plus an index of all terms.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TaxonomyConfig {
+    pub name: String,
+    /// URL prefix of the index and term pages; defaults to `name`.
+    pub path: Option<String>,
+    /// Term page template; defaults to `<name>/term.html`.
+    pub template: Option<String>,
+    /// Index template; defaults to `<name>/index.html`.
+    pub index_template: Option<String>,
+    /// Pages listed per term page; defaults to `pagination.per_page`.
+    pub paginate: Option<usize>,
+    /// Defaults to `pagination.path`.
+    pub paginate_path: Option<String>,
+    pub sort: SortBy,
+    /// Order of the terms on the index page.
+    pub index_sort: IndexSort,
+    /// Also render `feed.xml` for every term.
+    pub feed: bool,
+    /// Alternative spellings merged into one term, e.g. `js = "JavaScript"`.
+    /// The value is also the term's display name.
+    pub synonyms: BTreeMap<String, String>,
+    /// Write term and index pages. When off, terms are still collected but
+    /// nothing is output; `taxonomies = []` drops taxonomies altogether.
+    pub render: bool,
+}
+
+impl Default for TaxonomyConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            path: None,
+            template: None,
+            index_template: None,
+            paginate: None,
+            paginate_path: None,
+            sort: SortBy::default(),
+            index_sort: IndexSort::default(),
+            feed: false,
+            synonyms: BTreeMap::new(),
+            render: true,
+        }
+    }
+}
+
+impl TaxonomyConfig {
+    /// The built-in taxonomy used when `site.toml` declares none.
+    pub fn tags() -> Self {
+        Self {
+            name: "tags".to_string(),
+            template: Some("tag.html".to_string()),
+            index_template: Some("tags.html".to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(&self.name).trim_matches('/')
+    }
+
+    pub fn term_template(&self) -> String {
+        self.template
+            .clone()
+            .unwrap_or_else(|| format!("{}/term.html", self.name))
+    }
+
+    pub fn index_template(&self) -> String {
+        self.index_template
+            .clone()
+            .unwrap_or_else(|| format!("{}/index.html", self.name))
+    }
+
+    pub fn per_page(&self, defaults: &PaginationConfig) -> usize {
+        self.paginate.unwrap_or(defaults.per_page)
+    }
+
+    pub fn paginate_path<'a>(&'a self, defaults: &'a PaginationConfig) -> &'a str {
+        self.paginate_path
+            .as_deref()
+            .unwrap_or(&defaults.path)
+            .trim_matches('/')
+    }
+
+    /// Case-insensitive grouping key of `term`, after applying `synonyms`.
+    pub fn term_key(&self, term: &str) -> String {
+        let key = term.to_lowercase();
+        self.synonyms
+            .iter()
+            .find(|(from, _)| from.to_lowercase() == key)
+            .map_or(key, |(_, to)| to.to_lowercase())
+    }
+
+    /// Display name configured through `synonyms` for a term key, if any.
+    pub fn configured_name(&self, key: &str) -> Option<&str> {
+        self.synonyms
+            .values()
+            .find(|to| to.to_lowercase() == key)
+            .map(String::as_str)
+    }
+}
+
+/// Order of the pages listed on a term page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Newest first; undated pages last.
+    #[default]
+    Date,
+    Title,
+    /// Ascending `weight` from front matter; unweighted pages last.
+    Weight,
+}
+
+/// Order of the terms on a taxonomy index page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexSort {
+    #[default]
+    Name,
+    /// Most used terms first.
+    Count,
+}
+
+impl SiteConfig {
+    /// `themes/<theme>` under the source root, if that theme is installed.
+    pub fn theme_dir(&self) -> Option<PathBuf> {
+        let dir = self.src_dir.join("themes").join(&self.theme);
+        dir.is_dir().then_some(dir)
+    }
+
+    /// Template directories from lowest to highest precedence: the theme's,
+    /// then the site's own.
+    pub fn template_dirs(&self) -> Vec<PathBuf> {
+        let theme = self.theme_dir().map(|d| d.join("templates"));
+        theme
+            .into_iter()
+            .chain(std::iter::once(self.src_dir.join("templates")))
+            .collect()
+    }
+}
+
+/// A theme's `theme.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Defaults for the site's `extra` table.
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
 pub fn load_config<P: AsRef<Path>>(root: P) -> io::Result<SiteConfig> {
     let root = root.as_ref();
     let path = root.join("site.toml");
 
-    if !path.exists() {
-        return Ok(SiteConfig::default());
-    }
-
-    let text = fs::read_to_string(&path)?;
+    let mut config: SiteConfig = if path.exists() {
+        let text = fs::read_to_string(&path)?;
+        toml::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+    } else {
+        SiteConfig::default()
+    };
 
-    let mut config: SiteConfig =
-        toml::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let theme_toml = root.join("themes").join(&config.theme).join("theme.toml");
+    if theme_toml.exists() {
+        let text = fs::read_to_string(&theme_toml)?;
+        let theme: ThemeConfig = toml::from_str(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", theme_toml.display()),
+            )
+        })?;
+        for (key, value) in theme.extra {
+            match config.extra.get_mut(&key) {
+                Some(site) => merge_defaults(site, value),
+                None => {
+                    config.extra.insert(key, value);
+                }
+            }
+        }
+    }
 
     if config.src_dir.is_relative() {
         config.src_dir = root.join(&config.src_dir);
@@ -57,6 +356,22 @@ pub fn load_config<P: AsRef<Path>>(root: P) -> io::Result<SiteConfig> {
     Ok(config)
 }
 
+/// Fills in keys missing from `site` with those from `defaults`, recursing
+/// into tables; values the site sets always win.
+fn merge_defaults(site: &mut serde_json::Value, defaults: serde_json::Value) {
+    if let (serde_json::Value::Object(site), serde_json::Value::Object(defaults)) = (site, defaults)
+    {
+        for (key, value) in defaults {
+            match site.get_mut(&key) {
+                Some(existing) => merge_defaults(existing, value),
+                None => {
+                    site.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,6 +408,141 @@ mod tests {
         assert!(cfg.out_dir.ends_with("out"));
     }
 
+    #[test]
+    fn parses_permalinks_table() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("site.toml"),
+            r#"
+                ugly_urls = true
+                [permalinks]
+                posts = "/:year/:month/:slug/"
+                docs = "/:path/"
+            "#,
+        )
+        .unwrap();
+
+        let cfg = load_config(dir.path()).unwrap();
+        assert!(cfg.ugly_urls);
+        assert_eq!(cfg.permalinks["posts"], "/:year/:month/:slug/");
+        assert_eq!(cfg.permalinks["docs"], "/:path/");
+    }
+
+    #[test]
+    fn taxonomies_default_to_tags_and_can_be_declared() {
+        let cfg = SiteConfig::default();
+        assert_eq!(cfg.taxonomies, vec![TaxonomyConfig::tags()]);
+        assert_eq!(cfg.taxonomies[0].term_template(), "tag.html");
+
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("site.toml"),
+            r#"
+                [[taxonomies]]
+                name = "tags"
+                template = "tag.html"
+                index_template = "tags.html"
+
+                [pagination]
+                per_page = 20
+                path = "p"
+
+                [[taxonomies]]
+                name = "authors"
+                path = "/people/"
+                paginate = 5
+                paginate_path = "seite"
+                sort = "weight"
+                index_sort = "count"
+                feed = true
+            "#,
+        )
+        .unwrap();
+
+        let cfg = load_config(dir.path()).unwrap();
+        assert_eq!(cfg.taxonomies.len(), 2);
+        let authors = &cfg.taxonomies[1];
+        assert_eq!(authors.path(), "people");
+        assert_eq!(authors.term_template(), "authors/term.html");
+        assert_eq!(authors.index_template(), "authors/index.html");
+        assert_eq!(authors.per_page(&cfg.pagination), 5);
+        assert_eq!(authors.paginate_path(&cfg.pagination), "seite");
+        assert_eq!(cfg.taxonomies[0].per_page(&cfg.pagination), 20);
+        assert_eq!(cfg.taxonomies[0].paginate_path(&cfg.pagination), "p");
+        assert_eq!(cfg.pagination.window, 2);
+        assert_eq!(authors.sort, SortBy::Weight);
+        assert_eq!(authors.index_sort, IndexSort::Count);
+        assert!(authors.feed);
+    }
+
+    #[test]
+    fn taxonomy_output_can_be_disabled() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("site.toml"), "taxonomies = []").unwrap();
+        assert!(load_config(dir.path()).unwrap().taxonomies.is_empty());
+
+        fs::write(
+            dir.path().join("site.toml"),
+            "[[taxonomies]]\nname = \"tags\"\nrender = false",
+        )
+        .unwrap();
+        let cfg = load_config(dir.path()).unwrap();
+        assert!(!cfg.taxonomies[0].render);
+        assert!(TaxonomyConfig::tags().render);
+    }
+
+    #[test]
+    fn synonyms_merge_term_keys() {
+        let tax = TaxonomyConfig {
+            synonyms: [("js".to_string(), "JavaScript".to_string())].into(),
+            ..TaxonomyConfig::tags()
+        };
+        assert_eq!(tax.term_key("JS"), "javascript");
+        assert_eq!(tax.term_key("Rust"), "rust");
+        assert_eq!(tax.configured_name("javascript"), Some("JavaScript"));
+        assert_eq!(tax.configured_name("rust"), None);
+    }
+
+    #[test]
+    fn theme_extra_is_merged_under_site_extra() {
+        let dir = tempdir().unwrap();
+        let theme = dir.path().join("themes/plain");
+        fs::create_dir_all(theme.join("templates")).unwrap();
+        fs::write(
+            theme.join("theme.toml"),
+            r#"
+                name = "plain"
+                [extra]
+                accent = "blue"
+                [extra.menu]
+                home = "/"
+                blog = "/posts/"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("site.toml"),
+            r#"
+                theme = "plain"
+                src = "."
+                [extra]
+                accent = "red"
+                [extra.menu]
+                blog = "/blog/"
+            "#,
+        )
+        .unwrap();
+
+        let cfg = load_config(dir.path()).unwrap();
+        assert_eq!(cfg.extra["accent"], "red");
+        assert_eq!(cfg.extra["menu"]["home"], "/");
+        assert_eq!(cfg.extra["menu"]["blog"], "/blog/");
+        assert_eq!(
+            cfg.template_dirs(),
+            vec![theme.join("templates"), dir.path().join("./templates")]
+        );
+    }
+
     #[test]
     fn invalid_toml_is_invalid_data() {
         let dir = tempdir().unwrap();
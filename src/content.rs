@@ -1,16 +1,23 @@
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs, io,
     io::ErrorKind,
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
-pub fn collect_markdown_files<P: AsRef<Path>>(root: P) -> Vec<PathBuf> {
+use crate::ignores::IgnoreRules;
+
+pub fn collect_markdown_files<P: AsRef<Path>>(root: P, ignores: &IgnoreRules) -> Vec<PathBuf> {
     let mut result: Vec<PathBuf> = Vec::new();
+    // Themes ship their own READMEs and docs, which are not site content.
+    let themes = root.as_ref().join("themes");
 
-    for entry in WalkDir::new(root) {
+    for entry in WalkDir::new(&root).into_iter().filter_entry(|e| {
+        e.path() != themes && !ignores.is_ignored(e.path(), e.file_type().is_dir())
+    }) {
         let entry = match entry {
             Ok(e) => e,
             Err(err) => {
@@ -34,6 +41,34 @@ pub fn collect_markdown_files<P: AsRef<Path>>(root: P) -> Vec<PathBuf> {
     result
 }
 
+/// `paths` with every directory replaced by the files under it that are not
+/// ignored. A directory moved or copied into the site is reported by the
+/// watcher as a single path.
+pub fn expand_dirs(paths: &[PathBuf], ignores: &IgnoreRules) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let walk = WalkDir::new(path)
+            .into_iter()
+            .filter_entry(|e| !ignores.is_ignored(e.path(), e.file_type().is_dir()));
+        files.extend(
+            walk.filter_map(Result::ok)
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path()),
+        );
+    }
+    files
+}
+
+/// Whether `path` is a section page: an `_index.md` that renders with
+/// `section.html` and lists the other pages of its directory.
+pub fn is_section_index(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "_index.md")
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FrontMatterFormat {
     Yaml,
@@ -49,6 +84,38 @@ pub struct PageMeta {
     pub tags: Vec<String>,
     pub template: Option<String>,
     pub slug: Option<String>,
+    /// Explicit URL for the page, overriding slug and permalink rules.
+    #[serde(alias = "url")]
+    pub path: Option<String>,
+    /// Old URLs that should redirect to this page.
+    pub aliases: Vec<String>,
+    /// Position in listings sorted by weight; lower comes first.
+    pub weight: Option<i64>,
+    /// Pages listed per page of a section page; defaults to
+    /// `pagination.per_page`.
+    pub paginate: Option<usize>,
+    /// Defaults to `pagination.path`.
+    pub paginate_path: Option<String>,
+    /// Remaining front matter keys, such as custom taxonomies.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl PageMeta {
+    /// Terms listed under a taxonomy key, accepting a single string or a list.
+    pub fn terms(&self, taxonomy: &str) -> Vec<String> {
+        if taxonomy == "tags" {
+            return self.tags.clone();
+        }
+        match self.extra.get(taxonomy) {
+            Some(serde_json::Value::String(s)) => vec![s.clone()],
+            Some(serde_json::Value::Array(items)) => items
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -122,8 +189,16 @@ fn split_front_matter(text: &str) -> io::Result<(Option<(FrontMatterFormat, Stri
 pub fn load_document<M: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<Document<M>> {
     let path = path.as_ref();
     let content = fs::read_to_string(path)?;
+    parse_document(path, &content)
+}
 
-    let (front_matter, body) = split_front_matter(&content)?;
+/// Parses already-read file contents; `path` is only recorded on the document.
+pub fn parse_document<M: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    content: &str,
+) -> io::Result<Document<M>> {
+    let path = path.as_ref();
+    let (front_matter, body) = split_front_matter(content)?;
 
     let fm = match front_matter {
         Some((FrontMatterFormat::Yaml, fm_str)) => {
@@ -166,7 +241,7 @@ mod tests {
         fs::write(root.join("posts/a.md"), b"# a")?;
         fs::write(root.join("posts/sub/b.Md"), b"# b")?;
 
-        let mut got = collect_markdown_files(root);
+        let mut got = collect_markdown_files(root, &IgnoreRules::load(root, &[])?);
         got.sort(); // make comparison stable
 
         let mut expected = vec![
@@ -187,11 +262,28 @@ mod tests {
 
         fs::create_dir_all(root.join("empty/sub"))?;
 
-        let files = collect_markdown_files(root);
+        let files = collect_markdown_files(root, &IgnoreRules::load(root, &[])?);
         assert!(files.is_empty());
         Ok(())
     }
 
+    #[test]
+    fn skips_ignored_and_hidden_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+
+        fs::create_dir_all(root.join("drafts"))?;
+        fs::create_dir_all(root.join(".obsidian"))?;
+        fs::write(root.join("a.md"), b"# a")?;
+        fs::write(root.join("README.md"), b"# readme")?;
+        fs::write(root.join("drafts/b.md"), b"# b")?;
+        fs::write(root.join(".obsidian/c.md"), b"# c")?;
+
+        let ignores = IgnoreRules::load(root, &["drafts/".into(), "/README.md".into()])?;
+        assert_eq!(collect_markdown_files(root, &ignores), [root.join("a.md")]);
+        Ok(())
+    }
+
     #[test]
     fn doc_with_valid_yaml_front_matter() {
         let dir = tempdir().unwrap();
@@ -216,6 +308,23 @@ Body here.\n",
         assert_eq!(doc.body.lines().next().unwrap(), "# Heading");
     }
 
+    #[test]
+    fn custom_taxonomy_terms_from_front_matter() {
+        let dir = tempdir().unwrap();
+        let f = dir.path().join("post.md");
+        fs::write(
+            &f,
+            "---\ntitle: Hi\ntags: [rust]\ncategories: [Guides, Tools]\nauthors: Ada\n---\nbody\n",
+        )
+        .unwrap();
+
+        let meta = load_document::<PageMeta>(&f).unwrap().front_matter.unwrap();
+        assert_eq!(meta.terms("tags"), vec!["rust"]);
+        assert_eq!(meta.terms("categories"), vec!["Guides", "Tools"]);
+        assert_eq!(meta.terms("authors"), vec!["Ada"]);
+        assert!(meta.terms("series").is_empty());
+    }
+
     #[test]
     fn doc_with_no_front_matter() {
         let dir = tempdir().unwrap();
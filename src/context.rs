@@ -0,0 +1,303 @@
+//! The variables templates are rendered with.
+//!
+//! Every template, whatever it renders, sees the same site-wide variables:
+//!
+//! - `site`: the public part of `site.toml` ([`SiteView`]); local paths
+//!   such as `src` and `out` are never exposed.
+//! - `build`: when and how the site was built ([`BuildInfo`]).
+//! - `taxonomies`: every configured taxonomy with its terms and their
+//!   pages, keyed by taxonomy name.
+//! - `sections`: every content directory with its pages, keyed by the
+//!   directory path relative to the content root (`""` for the root).
+//! - `data`: the files under `data/`, nested by path (`data.team` for
+//!   `data/team.toml`).
+//! - `assets`: the URL and integrity hash of every static asset, keyed by
+//!   its path before fingerprinting (see [`AssetManifest`]).
+//!
+//! On top of those, each kind of output adds its own variables:
+//!
+//! - pages: `page`
+//! - section pages: `page` and `section`, with the pages of the listing
+//!   page at hand and its `pagination`
+//! - term pages: `taxonomy` and `term` (also `tag` for `tags`)
+//! - taxonomy indexes: `taxonomy` and `terms` (also `tags` for `tags`)
+//! - term feeds: `taxonomy` and `feed`
+//! - the 404 page: nothing else
+
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tera::{Context, Tera};
+
+use crate::{
+    assets::AssetManifest,
+    config::{SiteConfig, SortBy},
+    taxonomy::{PageSummary, TaxonomyListing, sort_pages, taxonomy_listing},
+};
+
+/// Whether the site is built for publishing or for the dev server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Production,
+    Development,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteView<'a> {
+    pub title: &'a str,
+    pub base_url: &'a str,
+    pub theme: &'a str,
+    pub description: &'a Option<String>,
+    pub author: &'a Option<String>,
+    /// `extra` from `site.toml`, on top of the theme's defaults.
+    pub extra: &'a BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    /// RFC 3339 timestamp of the start of the build.
+    pub time: String,
+    pub version: &'static str,
+    pub environment: Environment,
+}
+
+/// A content directory: its `index.md` (or `_index.md`) gives the title and
+/// URL, every other page directly inside it is listed in `pages`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SectionView {
+    pub path: String,
+    pub title: String,
+    pub url: String,
+    pub pages: Vec<PageSummary>,
+}
+
+#[derive(Serialize)]
+struct Globals<'a> {
+    site: SiteView<'a>,
+    build: BuildInfo,
+    taxonomies: BTreeMap<&'a str, TaxonomyListing>,
+    sections: BTreeMap<String, SectionView>,
+    data: serde_json::Value,
+    assets: &'a AssetManifest,
+}
+
+/// The site-wide variables, serialized once per build. Each rendering thread
+/// copies them once and reuses the copy for every render it runs, adding
+/// and removing only the variables of the render at hand.
+pub struct SiteContext {
+    id: u64,
+    ctx: Context,
+}
+
+thread_local! {
+    /// This thread's copy of the variables of the `SiteContext` with the id.
+    static BASE: RefCell<Option<(u64, Context)>> = const { RefCell::new(None) };
+}
+
+impl SiteContext {
+    pub fn new(
+        cfg: &SiteConfig,
+        env: Environment,
+        pages: &[PageSummary],
+        data: serde_json::Value,
+        assets: &AssetManifest,
+    ) -> Self {
+        let globals = Globals {
+            site: SiteView {
+                title: &cfg.title,
+                base_url: &cfg.base_url,
+                theme: &cfg.theme,
+                description: &cfg.description,
+                author: &cfg.author,
+                extra: &cfg.extra,
+            },
+            build: BuildInfo {
+                time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                version: env!("CARGO_PKG_VERSION"),
+                environment: env,
+            },
+            taxonomies: cfg
+                .taxonomies
+                .iter()
+                .map(|tax| (tax.name.as_str(), taxonomy_listing(cfg, tax, pages)))
+                .collect(),
+            sections: sections(pages),
+            data,
+            assets,
+        };
+        // Every field above serializes to a JSON object, which cannot fail.
+        let ctx = Context::from_serialize(globals).unwrap_or_default();
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            ctx,
+        }
+    }
+
+    /// A full copy of the site-wide variables.
+    pub fn context(&self) -> Context {
+        self.ctx.clone()
+    }
+
+    /// One site-wide variable.
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.ctx.get(key)
+    }
+
+    /// Renders `template` with the site-wide variables plus `vars`, which
+    /// take precedence.
+    pub fn render(&self, tera: &Tera, template: &str, vars: Context) -> tera::Result<String> {
+        BASE.with(|base| {
+            let mut base = base.borrow_mut();
+            let ctx = match &mut *base {
+                Some((id, ctx)) if *id == self.id => ctx,
+                slot => &mut slot.insert((self.id, self.ctx.clone())).1,
+            };
+            let vars = match vars.into_json() {
+                serde_json::Value::Object(vars) => vars,
+                _ => Default::default(),
+            };
+            let shadowed: Vec<(String, Option<serde_json::Value>)> = vars
+                .into_iter()
+                .map(|(key, value)| {
+                    let old = ctx.remove(&key);
+                    ctx.insert(key.as_str(), &value);
+                    (key, old)
+                })
+                .collect();
+            let html = tera.render(template, ctx);
+            for (key, old) in shadowed {
+                ctx.remove(&key);
+                if let Some(old) = old {
+                    ctx.insert(key, &old);
+                }
+            }
+            html
+        })
+    }
+}
+
+pub(crate) fn sections(pages: &[PageSummary]) -> BTreeMap<String, SectionView> {
+    let mut sections: BTreeMap<String, SectionView> = BTreeMap::new();
+    let mut listed: BTreeMap<String, Vec<&PageSummary>> = BTreeMap::new();
+    for page in pages {
+        let (dir, file) = page.path.rsplit_once('/').unwrap_or(("", &page.path));
+        let section = sections
+            .entry(dir.to_string())
+            .or_insert_with(|| SectionView {
+                path: dir.to_string(),
+                title: dir.rsplit('/').next().unwrap_or_default().to_string(),
+                url: if dir.is_empty() {
+                    "/".to_string()
+                } else {
+                    format!("/{dir}/")
+                },
+                pages: Vec::new(),
+            });
+        if matches!(file, "index.md" | "_index.md") {
+            section.title = page.title.clone();
+            section.url = page.url.clone();
+        } else {
+            listed.entry(dir.to_string()).or_default().push(page);
+        }
+    }
+    for (dir, mut items) in listed {
+        sort_pages(&mut items, SortBy::Date);
+        if let Some(section) = sections.get_mut(&dir) {
+            section.pages = items.into_iter().cloned().collect();
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(path: &str, title: &str, date: &str) -> PageSummary {
+        PageSummary {
+            title: title.into(),
+            url: format!("/{}/", path.trim_end_matches(".md")),
+            path: path.into(),
+            date: Some(date.into()),
+            tags: vec!["rust".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_globals_for_every_render() {
+        let cfg = SiteConfig {
+            title: "T".into(),
+            ..Default::default()
+        };
+        let pages = vec![
+            page("index.md", "Home", "2024-01-01"),
+            page("posts/index.md", "Blog", "2024-01-01"),
+            page("posts/old.md", "Old", "2023-01-01"),
+            page("posts/new.md", "New", "2025-01-01"),
+        ];
+        let data = serde_json::json!({ "team": [{ "name": "Ada" }] });
+        let mut assets = AssetManifest::default();
+        assets.entries.insert(
+            "app.css".into(),
+            crate::assets::AssetEntry {
+                url: "/app.0123456789.css".into(),
+                integrity: None,
+            },
+        );
+        let site = SiteContext::new(&cfg, Environment::Development, &pages, data, &assets);
+        let json = site.context().into_json();
+
+        assert_eq!(json["site"]["title"], "T");
+        assert!(json["site"].get("src").is_none());
+        assert_eq!(json["build"]["environment"], "development");
+        assert_eq!(json["build"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["taxonomies"]["tags"]["terms"][0]["count"], 4);
+
+        let posts = &json["sections"]["posts"];
+        assert_eq!(posts["title"], "Blog");
+        assert_eq!(posts["pages"][0]["title"], "New");
+        assert_eq!(posts["pages"][1]["title"], "Old");
+        assert_eq!(json["sections"][""]["title"], "Home");
+        assert_eq!(json["data"]["team"][0]["name"], "Ada");
+        assert_eq!(json["assets"]["app.css"]["url"], "/app.0123456789.css");
+    }
+
+    #[test]
+    fn render_variables_do_not_outlive_the_render() {
+        let cfg = SiteConfig {
+            title: "T".into(),
+            ..Default::default()
+        };
+        let site = SiteContext::new(
+            &cfg,
+            Environment::Production,
+            &[],
+            serde_json::json!({}),
+            &AssetManifest::default(),
+        );
+        let mut tera = Tera::default();
+        tera.add_raw_template(
+            "t",
+            "{{ site.title }}|{{ page.title | default(value='-') }}",
+        )
+        .unwrap();
+
+        let mut vars = Context::new();
+        vars.insert("page", &serde_json::json!({ "title": "A" }));
+        assert_eq!(site.render(&tera, "t", vars).unwrap(), "T|A");
+        assert_eq!(site.render(&tera, "t", Context::new()).unwrap(), "T|-");
+
+        // A render variable may shadow a site-wide one, but only for itself.
+        let mut vars = Context::new();
+        vars.insert("site", &serde_json::json!({ "title": "S" }));
+        assert_eq!(site.render(&tera, "t", vars).unwrap(), "S|-");
+        assert_eq!(site.render(&tera, "t", Context::new()).unwrap(), "T|-");
+    }
+}
@@ -0,0 +1,199 @@
+use serde_json::{Map, Value};
+use std::{fs, io, path::Path};
+use walkdir::WalkDir;
+
+/// Loads every data file under `dir` into one object nested like the
+/// directory tree: `data/team.toml` becomes `team`, `data/releases/matrix.csv`
+/// becomes `releases.matrix`. A missing directory gives an empty object.
+pub fn load_data_dir(dir: &Path) -> io::Result<Value> {
+    let mut root = Map::new();
+    let mut files: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && is_data_file(e.path()))
+        .map(|e| e.into_path())
+        .collect();
+    files.sort();
+
+    for file in files {
+        let rel = file.strip_prefix(dir).unwrap_or(&file).with_extension("");
+        let keys: Vec<String> = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let Some((last, parents)) = keys.split_last() else {
+            continue;
+        };
+        let conflict = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: `{}` is defined twice", file.display(), keys.join(".")),
+            )
+        };
+
+        let mut table = &mut root;
+        for key in parents {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            table = entry.as_object_mut().ok_or_else(conflict)?;
+        }
+        if table.contains_key(last) {
+            return Err(conflict());
+        }
+        table.insert(last.clone(), load_data_file(&file)?);
+    }
+    Ok(Value::Object(root))
+}
+
+/// Reads a TOML, YAML, JSON or CSV file into a JSON value, picking the format
+/// from the extension. CSV files become a list of objects keyed by the
+/// header row.
+pub fn load_data_file(path: &Path) -> io::Result<Value> {
+    let text = fs::read_to_string(path)?;
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let invalid = |e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    };
+    match ext.as_str() {
+        "toml" => toml::from_str(&text).map_err(|e| invalid(&e)),
+        "yaml" | "yml" => serde_yaml::from_str(&text).map_err(|e| invalid(&e)),
+        "json" => serde_json::from_str(&text).map_err(|e| invalid(&e)),
+        "csv" => parse_csv(&text).map_err(|e| invalid(&e)),
+        _ => Err(invalid(&"unsupported data format")),
+    }
+}
+
+/// Whether `path` has an extension `load_data_file` understands.
+pub fn is_data_file(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        ["toml", "yaml", "yml", "json", "csv"]
+            .iter()
+            .any(|d| e.eq_ignore_ascii_case(d))
+    })
+}
+
+fn parse_csv(text: &str) -> Result<Value, &'static str> {
+    let mut rows = csv_rows(text)?.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(Value::Array(Vec::new()));
+    };
+    let records = rows
+        .map(|row| {
+            let record: Map<String, Value> = header
+                .iter()
+                .cloned()
+                .zip(
+                    row.into_iter()
+                        .map(Value::String)
+                        .chain(std::iter::repeat(Value::Null)),
+                )
+                .collect();
+            Value::Object(record)
+        })
+        .collect();
+    Ok(Value::Array(records))
+}
+
+/// Splits RFC 4180 CSV into rows of fields, honouring quoted fields with
+/// embedded separators, newlines and doubled quotes. Blank lines are skipped.
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, &'static str> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field");
+    }
+    row.push(field);
+    if row.iter().any(|f| !f.is_empty()) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn csv_rows_become_objects() {
+        let v = parse_csv("name,quote\r\nAda,\"Hello, \"\"world\"\"\"\n\nLinus,\"multi\nline\"\n")
+            .unwrap();
+        assert_eq!(v[0]["name"], "Ada");
+        assert_eq!(v[0]["quote"], "Hello, \"world\"");
+        assert_eq!(v[1]["quote"], "multi\nline");
+        assert_eq!(v.as_array().unwrap().len(), 2);
+        assert!(parse_csv("a\n\"open").is_err());
+    }
+
+    #[test]
+    fn format_follows_extension() {
+        let dir = tempdir().unwrap();
+        let toml = dir.path().join("a.toml");
+        fs::write(&toml, "title = \"x\"\n[nav]\nhome = \"/\"").unwrap();
+        assert_eq!(load_data_file(&toml).unwrap()["nav"]["home"], "/");
+
+        let yaml = dir.path().join("b.yml");
+        fs::write(&yaml, "- 1\n- 2\n").unwrap();
+        assert_eq!(load_data_file(&yaml).unwrap()[1], 2);
+
+        let txt = dir.path().join("c.txt");
+        fs::write(&txt, "x").unwrap();
+        let err = load_data_file(&txt).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!is_data_file(&txt));
+    }
+
+    #[test]
+    fn data_dir_nests_by_path() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("releases")).unwrap();
+        fs::write(data.join("team.yaml"), "- name: Ada\n").unwrap();
+        fs::write(data.join("releases/matrix.csv"), "os,ok\nlinux,yes\n").unwrap();
+        fs::write(data.join("notes.txt"), "ignored").unwrap();
+
+        let v = load_data_dir(&data).unwrap();
+        assert_eq!(v["team"][0]["name"], "Ada");
+        assert_eq!(v["releases"]["matrix"][0]["os"], "linux");
+        assert!(v.get("notes").is_none());
+
+        fs::write(data.join("team.json"), "[]").unwrap();
+        assert!(load_data_dir(&data).is_err());
+
+        let missing = load_data_dir(&dir.path().join("nope")).unwrap();
+        assert_eq!(missing, Value::Object(Map::new()));
+    }
+}
@@ -0,0 +1,535 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::ignores::IGNORE_FILE;
+
+/// What the last build learned about which outputs depend on which inputs.
+///
+/// Templates are keyed by their Tera name (path relative to `templates/`),
+/// pages by their path relative to the source root.
+#[derive(Debug, Clone, Default)]
+pub struct DepGraph {
+    /// Template directories, lowest precedence first.
+    template_dirs: Vec<PathBuf>,
+    templates: HashMap<String, BTreeSet<String>>,
+    /// Templates that read the site's `data` directly.
+    data_templates: BTreeSet<String>,
+    /// Templates that look up asset URLs or integrity hashes, or process
+    /// images.
+    asset_templates: BTreeSet<String>,
+    /// Templates that list or look up other pages, through `sections`,
+    /// `taxonomies` or functions such as `get_page`.
+    listing_templates: BTreeSet<String>,
+    /// Files each template reads with `load_data`, relative to the site
+    /// root; `None` when a path is not a literal, so could be any file.
+    loaded_files: HashMap<String, Option<BTreeSet<String>>>,
+    pages: HashMap<String, PageDeps>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageDeps {
+    pub template: String,
+    /// `(taxonomy, term key)` pairs the page is listed under.
+    pub terms: Vec<(String, String)>,
+    /// Source directory of the page's bundle, relative to the site root.
+    pub bundle: Option<PathBuf>,
+}
+
+/// Outputs that have to be regenerated after a set of source changes.
+#[derive(Debug, Clone, Default)]
+pub struct Affected {
+    /// Pages (relative source paths) whose content changed.
+    pub pages: HashSet<String>,
+    /// Changed templates plus every template that extends, includes or imports them.
+    pub templates: HashSet<String>,
+    /// `(taxonomy, term key)` pairs whose term pages list a changed page.
+    pub terms: HashSet<(String, String)>,
+    /// Whether anything other than pages, templates or config changed.
+    pub assets: bool,
+}
+
+impl DepGraph {
+    /// Scans the templates under `dirs`; as when loading them, a later
+    /// directory overrides templates of the same name.
+    pub fn scan_templates(dirs: &[PathBuf]) -> Self {
+        let mut templates = HashMap::new();
+        let mut data_templates = BTreeSet::new();
+        let mut asset_templates = BTreeSet::new();
+        let mut listing_templates = BTreeSet::new();
+        let mut loaded_files = HashMap::new();
+        for dir in dirs {
+            for e in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+                if !e.file_type().is_file() {
+                    continue;
+                }
+                let Some(name) = template_name(dir, e.path()) else {
+                    continue;
+                };
+                let text = fs::read_to_string(e.path()).unwrap_or_default();
+                // A site template replaces the theme's, so drop what the
+                // theme version used.
+                for (set, used) in [
+                    (&mut data_templates, mentions_var(&text, "data")),
+                    (&mut asset_templates, mentions_assets(&text)),
+                    (&mut listing_templates, mentions_listings(&text)),
+                ] {
+                    if used {
+                        set.insert(name.clone());
+                    } else {
+                        set.remove(&name);
+                    }
+                }
+                match load_data_paths(&text) {
+                    Some(files) if files.is_empty() => loaded_files.remove(&name),
+                    files => loaded_files.insert(name.clone(), files),
+                };
+                templates.insert(name, template_refs(&text));
+            }
+        }
+        Self {
+            template_dirs: dirs.to_vec(),
+            templates,
+            data_templates,
+            asset_templates,
+            listing_templates,
+            loaded_files,
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn insert_page(&mut self, rel: String, deps: PageDeps) {
+        self.pages.insert(rel, deps);
+    }
+
+    pub fn page(&self, rel: &str) -> Option<&PageDeps> {
+        self.pages.get(rel)
+    }
+
+    /// `name` and every template it pulls in, directly or transitively.
+    pub fn template_closure(&self, name: &str) -> BTreeSet<String> {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![name.to_string()];
+        while let Some(t) = stack.pop() {
+            if let Some(refs) = self.templates.get(&t) {
+                stack.extend(refs.iter().filter(|r| !seen.contains(*r)).cloned());
+            }
+            seen.insert(t);
+        }
+        seen
+    }
+
+    /// Whether `name`, or a template it pulls in, reads `data`.
+    pub fn uses_data(&self, name: &str) -> bool {
+        !self
+            .template_closure(name)
+            .is_disjoint(&self.data_templates)
+    }
+
+    /// Whether `name`, or a template it pulls in, looks up assets or
+    /// processes images.
+    pub fn uses_assets(&self, name: &str) -> bool {
+        !self
+            .template_closure(name)
+            .is_disjoint(&self.asset_templates)
+    }
+
+    /// Whether `name`, or a template it pulls in, lists other pages, so
+    /// that any page's title, URL, date or terms show up in its output.
+    pub fn uses_listings(&self, name: &str) -> bool {
+        !self
+            .template_closure(name)
+            .is_disjoint(&self.listing_templates)
+    }
+
+    /// Files `name`, or a template it pulls in, reads with `load_data`.
+    /// `None` means they cannot be known without rendering.
+    pub fn loaded_files(&self, name: &str) -> Option<BTreeSet<String>> {
+        let mut files = BTreeSet::new();
+        for t in self.template_closure(name) {
+            if let Some(loaded) = self.loaded_files.get(&t) {
+                files.extend(loaded.as_ref()?.iter().cloned());
+            }
+        }
+        Some(files)
+    }
+
+    /// Works out what a set of changed source paths invalidates.
+    ///
+    /// Returns `None` when the change cannot be tracked precisely (the site
+    /// or theme config or the ignore file changed) and everything has to be
+    /// rebuilt.
+    pub fn affected(&self, src_root: &Path, changed: &[PathBuf]) -> Option<Affected> {
+        let mut affected = Affected::default();
+        let mut changed_templates = BTreeSet::new();
+
+        for path in changed {
+            let Ok(rel) = path.strip_prefix(src_root) else {
+                continue;
+            };
+            if rel == Path::new("site.toml")
+                || rel == Path::new(IGNORE_FILE)
+                || rel.ends_with("theme.toml")
+            {
+                return None;
+            }
+            let name = self
+                .template_dirs
+                .iter()
+                .find_map(|dir| template_name(dir, path));
+            if name.is_none() {
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                let loading = self
+                    .loaded_files
+                    .iter()
+                    .filter(|(_, files)| files.as_ref().is_none_or(|files| files.contains(&rel)));
+                changed_templates.extend(loading.map(|(t, _)| t.clone()));
+            }
+            if let Some(name) = name {
+                changed_templates.insert(name);
+            } else if rel.starts_with("data") {
+                changed_templates.extend(self.data_templates.iter().cloned());
+            } else if is_markdown(path) {
+                let rel = rel.to_string_lossy().to_string();
+                if let Some(prev) = self.pages.get(&rel) {
+                    affected.terms.extend(prev.terms.iter().cloned());
+                }
+                affected.pages.insert(rel);
+                // The page may have been added, removed or retitled.
+                changed_templates.extend(self.listing_templates.iter().cloned());
+            } else {
+                // The asset's URL or integrity hash may have changed, and so
+                // may the `page.assets` of the bundle it is in.
+                changed_templates.extend(self.asset_templates.iter().cloned());
+                let bundled = self
+                    .pages
+                    .iter()
+                    .filter(|(_, deps)| deps.bundle.as_ref().is_some_and(|b| rel.starts_with(b)));
+                affected.pages.extend(bundled.map(|(page, _)| page.clone()));
+                affected.assets = true;
+                // A directory that was removed or moved away takes its pages
+                // with it.
+                for (page, deps) in &self.pages {
+                    if Path::new(page).starts_with(rel) {
+                        affected.terms.extend(deps.terms.iter().cloned());
+                        affected.pages.insert(page.clone());
+                        changed_templates.extend(self.listing_templates.iter().cloned());
+                    }
+                }
+            }
+        }
+
+        if !changed_templates.is_empty() {
+            affected.templates = self
+                .templates
+                .keys()
+                .filter(|t| !self.template_closure(t).is_disjoint(&changed_templates))
+                .cloned()
+                .collect();
+            affected.templates.extend(changed_templates);
+        }
+
+        Some(affected)
+    }
+}
+
+impl Affected {
+    pub fn page_needs_render(&self, rel: &str, template: &str) -> bool {
+        self.pages.contains(rel) || self.templates.contains(template)
+    }
+}
+
+fn template_name(tpl_dir: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(tpl_dir).ok()?;
+    Some(rel.to_string_lossy().replace('\\', "/"))
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("md"))
+}
+
+/// Whether a template reads the top-level variable `var`. This is a plain
+/// text scan, so a mention inside a comment also counts, which only costs a
+/// re-render.
+fn mentions_var(text: &str, var: &str) -> bool {
+    text.match_indices(var).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + var.len()..].chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.')
+            && matches!(after, Some('.' | '['))
+    })
+}
+
+fn mentions_assets(text: &str) -> bool {
+    [
+        "asset_url",
+        "asset_integrity",
+        "resize_image",
+        "image_srcset",
+        "get_image_metadata",
+    ]
+    .iter()
+    .any(|f| text.contains(f))
+        || mentions_var(text, "assets")
+}
+
+fn mentions_listings(text: &str) -> bool {
+    ["get_page", "get_section", "get_taxonomy", "get_url"]
+        .iter()
+        .any(|f| text.contains(f))
+        || ["sections", "taxonomies"]
+            .iter()
+            .any(|var| mentions_var(text, var) || loops_over(text, var))
+}
+
+/// The literal `path` arguments of every `load_data` call, or `None` if
+/// any call passes something else.
+fn load_data_paths(text: &str) -> Option<BTreeSet<String>> {
+    let mut paths = BTreeSet::new();
+    for (i, call) in text.match_indices("load_data(") {
+        let args = &text[i + call.len()..];
+        let args = &args[..args.find(')').unwrap_or(args.len())];
+        let (_, value) = args.split_once("path")?;
+        let value = value.trim_start().strip_prefix('=')?.trim_start();
+        if !value.starts_with(['"', '\'', '`']) {
+            return None;
+        }
+        let path = quoted(value).into_iter().next()?;
+        paths.insert(path.trim_start_matches('/').to_string());
+    }
+    Some(paths)
+}
+
+/// Whether a template has a `{% for … in var %}` loop, which reads `var`
+/// without a `.` or `[` after it.
+fn loops_over(text: &str, var: &str) -> bool {
+    text.match_indices(&format!("in {var}")).any(|(i, m)| {
+        let after = text[i + m.len()..].chars().next();
+        !after.is_some_and(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// Template names referenced by `extends`, `include` and `import` tags.
+fn template_refs(text: &str) -> BTreeSet<String> {
+    let mut refs = BTreeSet::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{%") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("%}") else {
+            break;
+        };
+        let tag = after[..end].trim_matches(|c: char| c == '-' || c.is_whitespace());
+        rest = &after[end + 2..];
+
+        let (keyword, args) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        match keyword {
+            "extends" | "include" => refs.extend(quoted(args)),
+            "import" => refs.extend(quoted(args).into_iter().take(1)),
+            _ => {}
+        }
+    }
+    refs
+}
+
+fn quoted(s: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    while let Some(i) = rest.find(['"', '\'', '`']) {
+        // Quote characters are ASCII, so `i + 1` is a char boundary.
+        let quote = &rest[i..i + 1];
+        let tail = &rest[i + 1..];
+        let Some(len) = tail.find(quote) else {
+            break;
+        };
+        out.push(tail[..len].to_string());
+        rest = &tail[len + 1..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn quoted_skips_whole_strings_with_multibyte_text() {
+        assert_eq!(
+            quoted(r#"["héllo wörld", 'nav.html']"#),
+            ["héllo wörld", "nav.html"]
+        );
+    }
+
+    #[test]
+    fn finds_extends_include_and_import() {
+        let refs = template_refs(
+            r#"{% extends "base.html" %}
+            {%- include ['nav.html', "footer.html"] ignore missing -%}
+            {% import "macros.html" as m %}
+            {% block content %}{{ page.title }}{% endblock %}"#,
+        );
+        let want: BTreeSet<String> = ["base.html", "nav.html", "footer.html", "macros.html"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(refs, want);
+    }
+
+    #[test]
+    fn template_change_affects_dependents_only() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path();
+        let tpl = src.join("templates");
+        fs::create_dir_all(&tpl).unwrap();
+        fs::write(tpl.join("base.html"), "{% include \"nav.html\" %}").unwrap();
+        fs::write(tpl.join("nav.html"), "nav").unwrap();
+        fs::write(tpl.join("post.html"), "{% extends \"base.html\" %}").unwrap();
+        fs::write(tpl.join("tag.html"), "standalone").unwrap();
+
+        let mut graph = DepGraph::scan_templates(std::slice::from_ref(&tpl));
+        graph.insert_page(
+            "posts/a.md".into(),
+            PageDeps {
+                template: "post.html".into(),
+                terms: vec![("tags".into(), "rust".into())],
+                bundle: None,
+            },
+        );
+
+        let a = graph.affected(src, &[tpl.join("nav.html")]).unwrap();
+        assert!(a.page_needs_render("posts/a.md", "post.html"));
+        assert!(!a.templates.contains("tag.html"));
+
+        let a = graph.affected(src, &[src.join("posts/a.md")]).unwrap();
+        assert!(a.templates.is_empty());
+        assert!(a.terms.contains(&("tags".into(), "rust".into())));
+        assert!(!a.page_needs_render("posts/b.md", "post.html"));
+
+        assert!(graph.affected(src, &[src.join("site.toml")]).is_none());
+        assert!(graph.affected(src, &[src.join(IGNORE_FILE)]).is_none());
+    }
+
+    #[test]
+    fn data_change_affects_templates_reading_data() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path();
+        let tpl = src.join("templates");
+        fs::create_dir_all(&tpl).unwrap();
+        fs::write(
+            tpl.join("team.html"),
+            "{% for m in data.team %}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(tpl.join("about.html"), r#"{% extends "team.html" %}"#).unwrap();
+        fs::write(tpl.join("post.html"), "{{ page.metadata.x }}").unwrap();
+
+        let graph = DepGraph::scan_templates(std::slice::from_ref(&tpl));
+        assert!(graph.uses_data("about.html"));
+        assert!(!graph.uses_data("post.html"));
+
+        let a = graph.affected(src, &[src.join("data/team.toml")]).unwrap();
+        assert!(a.page_needs_render("about.md", "about.html"));
+        assert!(!a.page_needs_render("posts/a.md", "post.html"));
+        assert!(!a.assets);
+    }
+
+    #[test]
+    fn asset_change_affects_templates_using_asset_urls() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path();
+        let tpl = src.join("templates");
+        fs::create_dir_all(&tpl).unwrap();
+        fs::write(tpl.join("base.html"), r#"{{ asset_url(path="app.css") }}"#).unwrap();
+        fs::write(tpl.join("post.html"), r#"{% extends "base.html" %}"#).unwrap();
+        fs::write(tpl.join("plain.html"), "no assets here").unwrap();
+
+        let graph = DepGraph::scan_templates(std::slice::from_ref(&tpl));
+        assert!(graph.uses_assets("post.html"));
+        assert!(!graph.uses_assets("plain.html"));
+
+        let a = graph.affected(src, &[src.join("app.css")]).unwrap();
+        assert!(a.assets);
+        assert!(a.page_needs_render("a.md", "post.html"));
+        assert!(!a.page_needs_render("b.md", "plain.html"));
+    }
+
+    #[test]
+    fn page_change_affects_templates_listing_pages() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path();
+        let tpl = src.join("templates");
+        fs::create_dir_all(&tpl).unwrap();
+        fs::write(
+            tpl.join("index.html"),
+            "{% for p in sections.posts.pages %}{{ p.title }}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(
+            tpl.join("tags.html"),
+            "{% for name, t in taxonomies %}{{ name }}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(tpl.join("post.html"), "{{ page.title }}").unwrap();
+
+        let graph = DepGraph::scan_templates(std::slice::from_ref(&tpl));
+        assert!(graph.uses_listings("index.html"));
+        assert!(graph.uses_listings("tags.html"));
+        assert!(!graph.uses_listings("post.html"));
+
+        let a = graph.affected(src, &[src.join("posts/first.md")]).unwrap();
+        assert!(a.page_needs_render("index.md", "index.html"));
+        assert!(a.page_needs_render("about.md", "tags.html"));
+        assert!(!a.page_needs_render("posts/other.md", "post.html"));
+    }
+
+    #[test]
+    fn load_data_files_are_template_dependencies() {
+        assert_eq!(
+            load_data_paths(r#"{% set t = load_data(path="/data/team.csv") %}"#),
+            Some(BTreeSet::from(["data/team.csv".to_string()]))
+        );
+        assert_eq!(
+            load_data_paths("{{ load_data(path=page.extra.file) }}"),
+            None
+        );
+        assert_eq!(load_data_paths("no calls"), Some(BTreeSet::new()));
+
+        let tmp = tempdir().unwrap();
+        let src = tmp.path();
+        let tpl = src.join("templates");
+        fs::create_dir_all(&tpl).unwrap();
+        fs::write(
+            tpl.join("base.html"),
+            r#"{{ load_data(path="stats.json") }}"#,
+        )
+        .unwrap();
+        fs::write(tpl.join("post.html"), r#"{% extends "base.html" %}"#).unwrap();
+        fs::write(tpl.join("any.html"), "{{ load_data(path=page.slug) }}").unwrap();
+        fs::write(
+            tpl.join("plain.html"),
+            r#"{{ get_page(path="a.md").title }}"#,
+        )
+        .unwrap();
+
+        let graph = DepGraph::scan_templates(std::slice::from_ref(&tpl));
+        assert_eq!(
+            graph.loaded_files("post.html"),
+            Some(BTreeSet::from(["stats.json".to_string()]))
+        );
+        assert_eq!(graph.loaded_files("any.html"), None);
+        assert!(graph.uses_listings("plain.html"));
+
+        let a = graph.affected(src, &[src.join("stats.json")]).unwrap();
+        assert!(a.page_needs_render("a.md", "post.html"));
+        assert!(a.page_needs_render("b.md", "any.html"));
+        assert!(!a.page_needs_render("c.md", "plain.html"));
+
+        let a = graph.affected(src, &[src.join("b.md")]).unwrap();
+        assert!(a.page_needs_render("c.md", "plain.html"));
+    }
+}
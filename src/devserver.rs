@@ -3,14 +3,19 @@ use std::{
     path::{Path, PathBuf},
     sync::mpsc,
     thread,
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use mime_guess::from_path;
 use notify_debouncer_mini::notify::RecursiveMode;
 use notify_debouncer_mini::{DebounceEventResult, new_debouncer};
-use tiny_http::{Header, Response, Server};
-use walkdir::WalkDir;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    config::load_config,
+    context::Environment,
+    ignores::{IGNORE_FILE, IgnoreRules},
+};
 
 pub fn serve(src: &Path, out: &Path) {
     let manifest_root = std::env::var("CARGO_MANIFEST_DIR")
@@ -29,49 +34,67 @@ pub fn serve(src: &Path, out: &Path) {
     };
 
     let _ = fs::create_dir_all(&out);
-
-    crate::build(&src, &out);
+    let src = fs::canonicalize(&src).unwrap_or(src);
 
     let mut last_build = SystemTime::now();
-    let (tx, rx) = mpsc::channel::<()>();
+    let mut graph = crate::build(&src, &out, Environment::Development, None).map(|b| b.graph);
+
+    let (tx, rx) = mpsc::channel::<Vec<PathBuf>>();
     let _watcher = spawn_watcher(src.clone(), out.clone(), tx.clone());
     let server_thread = spawn_http(out.clone());
 
     println!("Dev server: http://127.0.0.1:4000  (Ctrl+C to quit)");
 
-    loop {
-        if rx.recv().is_ok() {
-            while rx.try_recv().is_ok() {}
-            if !has_changes_since(&src, last_build) {
-                continue;
-            }
-            println!("↻ Rebuilding…");
-            crate::build(&src, &out);
-            last_build = SystemTime::now();
-            println!("✓ Rebuilt");
-            std::thread::sleep(Duration::from_millis(100));
-            while rx.try_recv().is_ok() {}
-        } else {
-            break;
+    while let Ok(first) = rx.recv() {
+        // An empty batch means the watcher lost track of what changed.
+        let mut full = first.is_empty();
+        let mut changed = first;
+        while let Ok(more) = rx.try_recv() {
+            full |= more.is_empty();
+            changed.extend(more);
+        }
+        // Reading sources during a build raises events too; only keep paths
+        // that were modified or removed since the last build started.
+        changed.retain(|p| changed_since(p, last_build));
+        changed.sort();
+        changed.dedup();
+        if changed.is_empty() && !full {
+            continue;
         }
+
+        println!("↻ Rebuilding…");
+        let changes = match &graph {
+            Some(prev) if !full => Some((prev, changed.as_slice())),
+            _ => None,
+        };
+        last_build = SystemTime::now();
+        graph = crate::build(&src, &out, Environment::Development, changes).map(|b| b.graph);
+        println!("✓ Rebuilt");
     }
 
     let _ = server_thread.join();
 }
 
-fn spawn_watcher(src_dir: PathBuf, out_dir: PathBuf, tx: mpsc::Sender<()>) -> Option<impl Drop> {
+fn spawn_watcher(
+    src_dir: PathBuf,
+    out_dir: PathBuf,
+    tx: mpsc::Sender<Vec<PathBuf>>,
+) -> Option<impl Drop> {
     let src_dir = std::fs::canonicalize(&src_dir).ok()?;
     let out_dir = std::fs::canonicalize(&out_dir).unwrap_or(out_dir);
 
     let src_dir_cb = src_dir.clone();
     let out_dir_cb = out_dir.clone();
+    let ignore_file = src_dir.join(IGNORE_FILE);
+    let site_toml = src_dir.join("site.toml");
+    let mut ignores = watch_rules(&src_dir);
     let mut debouncer = new_debouncer(
         Duration::from_millis(500),
         move |res: DebounceEventResult| match res {
             Ok(events) => {
-                let mut trigger = false;
+                let mut changed = Vec::new();
                 for e in events {
-                    let Ok(p) = std::fs::canonicalize(&e.path) else {
+                    let Some(p) = canonical_event_path(&e.path) else {
                         continue;
                     };
                     if p.starts_with(&out_dir_cb) {
@@ -80,27 +103,31 @@ fn spawn_watcher(src_dir: PathBuf, out_dir: PathBuf, tx: mpsc::Sender<()>) -> Op
                     if !p.starts_with(&src_dir_cb) {
                         continue;
                     }
-                    if let Some(name) = p.file_name().and_then(|n| n.to_str())
-                        && (name.starts_with('.') || name.ends_with('~') || name.ends_with(".swp"))
-                    {
-                        continue;
+                    // Either file can change what is ignored.
+                    if p == ignore_file || p == site_toml {
+                        ignores = watch_rules(&src_dir_cb);
+                        if p == ignore_file {
+                            changed.push(p);
+                            continue;
+                        }
                     }
-                    let ok_ext = matches!(
-                        p.extension().and_then(|e| e.to_str()),
-                        Some("md" | "toml" | "html" | "css" | "js")
-                    );
-                    if !ok_ext {
+                    // Anything else may be content, data, a template or an
+                    // asset; hidden files such as editor swap files are
+                    // ignored by default.
+                    if ignores
+                        .as_ref()
+                        .is_some_and(|rules| rules.is_ignored(&p, p.is_dir()))
+                    {
                         continue;
                     }
-                    trigger = true;
-                    break;
+                    changed.push(p);
                 }
-                if trigger {
-                    let _ = tx.send(());
+                if !changed.is_empty() {
+                    let _ = tx.send(changed);
                 }
             }
             Err(_) => {
-                let _ = tx.send(());
+                let _ = tx.send(Vec::new());
             }
         },
     )
@@ -116,56 +143,327 @@ fn spawn_watcher(src_dir: PathBuf, out_dir: PathBuf, tx: mpsc::Sender<()>) -> Op
     Some(debouncer)
 }
 
+/// The site's ignore rules, so edits to ignored files don't trigger builds.
+/// `None` while `.ssgignore` or `site.toml` is broken; the build reports it.
+fn watch_rules(src_dir: &Path) -> Option<IgnoreRules> {
+    let patterns = load_config(src_dir).ok()?.ignore;
+    IgnoreRules::load(src_dir, &patterns).ok()
+}
+
+/// Canonicalizes an event path, falling back to its parent for files that
+/// were just removed so deletions are still reported.
+fn canonical_event_path(p: &Path) -> Option<PathBuf> {
+    if let Ok(real) = fs::canonicalize(p) {
+        return Some(real);
+    }
+    let parent = fs::canonicalize(p.parent()?).ok()?;
+    Some(parent.join(p.file_name()?))
+}
+
 fn spawn_http(out: PathBuf) -> thread::JoinHandle<()> {
     thread::spawn(move || {
         let server = Server::http("127.0.0.1:4000").expect("bind 127.0.0.1:4000");
+        let root = fs::canonicalize(&out).unwrap_or(out);
         for rq in server.incoming_requests() {
-            let url = rq.url().trim_start_matches('/');
-            let mut path = out.join(url);
-            if rq.url().ends_with('/') || is_dir(&path) {
-                path = out.join(url).join("index.html");
+            if !matches!(rq.method(), Method::Get | Method::Head) {
+                let resp = Response::from_data(b"405 Method Not Allowed".as_slice())
+                    .with_status_code(405)
+                    .with_header(header("Allow", "GET, HEAD"));
+                let _ = rq.respond(resp);
+                continue;
             }
-            match fs::File::open(&path) {
-                Ok(file) => {
-                    let mime = from_path(&path).first_or_octet_stream();
-                    let hdr = Header::from_bytes(&b"Content-Type"[..], mime.as_ref()).unwrap();
-                    let mut resp = Response::from_file(file);
-                    resp.add_header(hdr);
+
+            match resolve_request(&root, rq.url()) {
+                Resolved::File(path) => respond_file(rq, &path),
+                Resolved::Redirect(location) => {
+                    let resp = Response::empty(301).with_header(header("Location", &location));
                     let _ = rq.respond(resp);
                 }
-                Err(_) => {
-                    let body = b"404 Not Found";
-                    let _ = rq.respond(Response::from_data(body.as_slice()).with_status_code(404));
-                }
+                Resolved::NotFound => respond_not_found(rq, &root),
             }
         }
     })
 }
 
-fn has_changes_since(root: &Path, since: SystemTime) -> bool {
-    for entry in WalkDir::new(root) {
-        let Ok(e) = entry else { continue };
-        if !e.file_type().is_file() {
-            continue;
+#[derive(Debug, PartialEq, Eq)]
+enum Resolved {
+    File(PathBuf),
+    Redirect(String),
+    NotFound,
+}
+
+/// Maps a raw request target onto a file below `root`.
+///
+/// The path is percent-decoded and normalised before it touches the
+/// filesystem, and anything that would land outside `root` (`..`
+/// segments, symlinks) resolves to `NotFound`.
+fn resolve_request(root: &Path, raw_url: &str) -> Resolved {
+    let end = raw_url.find(['?', '#']).unwrap_or(raw_url.len());
+    let (raw_path, rest) = raw_url.split_at(end);
+    let query = if rest.starts_with('?') {
+        rest.split('#').next().unwrap_or("")
+    } else {
+        ""
+    };
+
+    let Some(decoded) = percent_decode(raw_path) else {
+        return Resolved::NotFound;
+    };
+    let Some(segments) = normalize_segments(&decoded) else {
+        return Resolved::NotFound;
+    };
+
+    let mut path = root.to_path_buf();
+    path.extend(&segments);
+
+    if is_dir(&path) {
+        if !decoded.ends_with('/') {
+            return Resolved::Redirect(format!("{raw_path}/{query}"));
         }
-        let p = e.path();
-        let ok_ext = matches!(
-            p.extension().and_then(|e| e.to_str()),
-            Some("md" | "toml" | "html" | "css" | "js")
-        );
-        if !ok_ext {
-            continue;
+        path.push("index.html");
+    }
+
+    match fs::canonicalize(&path) {
+        Ok(real) if real.starts_with(root) && real.is_file() => Resolved::File(real),
+        _ => Resolved::NotFound,
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // `from_str_radix` alone would accept a sign, as in `%+f`.
+            let hex = s.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
         }
-        if fs::metadata(p)
-            .and_then(|m| m.modified())
-            .is_ok_and(|m| m > since)
-        {
-            return true;
+    }
+    String::from_utf8(out).ok()
+}
+
+fn normalize_segments(path: &str) -> Option<Vec<&str>> {
+    let mut segments = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            s if s.contains(['\\', '\0', ':']) => return None,
+            s => segments.push(s),
         }
     }
-    false
+    Some(segments)
+}
+
+fn respond_file(rq: Request, path: &Path) {
+    let Ok(file) = fs::File::open(path) else {
+        let _ = rq.respond(Response::empty(500));
+        return;
+    };
+    let meta = file.metadata().ok();
+    let modified = meta.as_ref().and_then(|m| m.modified().ok());
+    let etag = meta.as_ref().map(|m| entity_tag(m.len(), modified));
+    let last_modified = modified.map(httpdate::fmt_http_date);
+
+    let mut validators = Vec::new();
+    if let Some(etag) = &etag {
+        validators.push(header("ETag", etag));
+    }
+    if let Some(lm) = &last_modified {
+        validators.push(header("Last-Modified", lm));
+    }
+    validators.push(header("Cache-Control", "no-cache"));
+
+    if is_not_modified(&rq, etag.as_deref(), modified) {
+        let mut resp = Response::empty(304);
+        for h in validators {
+            resp.add_header(h);
+        }
+        let _ = rq.respond(resp);
+        return;
+    }
+
+    let mime = from_path(path).first_or_octet_stream();
+    let mut resp = Response::from_file(file).with_header(header("Content-Type", mime.as_ref()));
+    for h in validators {
+        resp.add_header(h);
+    }
+    let _ = rq.respond(resp);
+}
+
+fn respond_not_found(rq: Request, root: &Path) {
+    // `404.md` renders to `404/index.html` under the default routing rules.
+    let page = [root.join("404.html"), root.join("404").join("index.html")]
+        .into_iter()
+        .find_map(|p| fs::File::open(p).ok());
+    match page {
+        Some(file) => {
+            let resp = Response::from_file(file)
+                .with_status_code(404)
+                .with_header(header("Content-Type", "text/html; charset=utf-8"));
+            let _ = rq.respond(resp);
+        }
+        None => {
+            let body = b"404 Not Found";
+            let _ = rq.respond(Response::from_data(body.as_slice()).with_status_code(404));
+        }
+    }
+}
+
+fn entity_tag(len: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{len:x}-{nanos:x}\"")
+}
+
+fn is_not_modified(rq: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    if let Some(inm) = request_header(rq, "If-None-Match") {
+        return etag.is_some_and(|etag| {
+            inm.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag)
+        });
+    }
+    let since =
+        request_header(rq, "If-Modified-Since").and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, modified) {
+        // HTTP dates have one-second resolution, so compare whole seconds.
+        (Some(since), Some(modified)) => {
+            let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            secs(modified) <= secs(since)
+        }
+        _ => false,
+    }
+}
+
+fn request_header<'a>(rq: &'a Request, name: &'static str) -> Option<&'a str> {
+    rq.headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn changed_since(p: &Path, since: SystemTime) -> bool {
+    match fs::metadata(p) {
+        Ok(m) => m.modified().is_ok_and(|m| m > since),
+        Err(_) => true,
+    }
 }
 
 fn is_dir(p: &Path) -> bool {
     fs::metadata(p).map(|m| m.is_dir()).unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn site() -> (tempfile::TempDir, PathBuf) {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("out");
+        fs::create_dir_all(root.join("posts/first")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("posts/first/index.html"), "first").unwrap();
+        fs::write(root.join("a b.txt"), "spaced").unwrap();
+        fs::write(tmp.path().join("secret.txt"), "nope").unwrap();
+        let root = fs::canonicalize(root).unwrap();
+        (tmp, root)
+    }
+
+    #[test]
+    fn resolves_index_files_and_strips_query() {
+        let (_tmp, root) = site();
+        assert_eq!(
+            resolve_request(&root, "/"),
+            Resolved::File(root.join("index.html"))
+        );
+        assert_eq!(
+            resolve_request(&root, "/posts/first/?x=1#top"),
+            Resolved::File(root.join("posts/first/index.html"))
+        );
+        assert_eq!(
+            resolve_request(&root, "/a%20b.txt"),
+            Resolved::File(root.join("a b.txt"))
+        );
+    }
+
+    #[test]
+    fn redirects_directories_without_trailing_slash() {
+        let (_tmp, root) = site();
+        assert_eq!(
+            resolve_request(&root, "/posts/first?x=1"),
+            Resolved::Redirect("/posts/first/?x=1".into())
+        );
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_root() {
+        let (_tmp, root) = site();
+        assert_eq!(resolve_request(&root, "/../secret.txt"), Resolved::NotFound);
+        assert_eq!(
+            resolve_request(&root, "/%2e%2e/secret.txt"),
+            Resolved::NotFound
+        );
+        assert_eq!(
+            resolve_request(&root, "/posts/%2E%2E/%2E%2E/secret.txt"),
+            Resolved::NotFound
+        );
+        assert_eq!(
+            resolve_request(&root, "/..%5csecret.txt"),
+            Resolved::NotFound
+        );
+        assert_eq!(resolve_request(&root, "/%zz"), Resolved::NotFound);
+        assert_eq!(percent_decode("/a%+f"), None);
+        assert_eq!(percent_decode("/a%2Fb").as_deref(), Some("/a/b"));
+        assert_eq!(resolve_request(&root, "/missing.html"), Resolved::NotFound);
+    }
+
+    #[test]
+    fn watched_builds_render_new_missing_and_moved_in_pages() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.path().join("site");
+        let out = tmp.path().join("out");
+        fs::create_dir_all(src.join("templates")).unwrap();
+        fs::write(src.join("templates/post.html"), "{{ page.title }}").unwrap();
+        fs::write(src.join("site.toml"), "title = \"T\"\n").unwrap();
+        fs::write(src.join("a.md"), "---\ntitle: A\n---\n").unwrap();
+        let graph = crate::build(&src, &out, Environment::Development, None)
+            .unwrap()
+            .graph;
+
+        // Only the directory moved in is reported, yet its posts are
+        // rendered and the deleted output comes back.
+        fs::remove_file(out.join("a/index.html")).unwrap();
+        fs::create_dir_all(src.join("posts")).unwrap();
+        fs::write(src.join("posts/b.md"), "---\ntitle: B\n---\n").unwrap();
+        let changed = [src.join("posts")];
+        crate::build(
+            &src,
+            &out,
+            Environment::Development,
+            Some((&graph, &changed)),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(out.join("a/index.html")).unwrap(), "A");
+        assert_eq!(
+            fs::read_to_string(out.join("posts/b/index.html")).unwrap(),
+            "B"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Site-aware functions and filters available in every template.
+//!
+//! Functions:
+//!
+//! - `get_url(path)`: absolute URL of a page given its source path
+//!   (`posts/a.md`), or of any other site-relative path.
+//! - `get_page(path)`: summary of the page at a source path.
+//! - `get_section(path)`: a content directory, as in `sections`.
+//! - `get_taxonomy(kind)`: a taxonomy with its terms, as in `taxonomies`.
+//! - `load_data(path)`: a TOML, YAML, JSON or CSV file under the source root.
+//! - `asset_url(path)`: URL of a static asset, fingerprinted if enabled.
+//! - `asset_integrity(path)`: its Subresource Integrity hash, for the
+//!   extensions listed under `[assets]`.
+//! - `resize_image(path, width, height, format, quality)`: a resized and/or
+//!   converted copy of an image, with its URL and dimensions. `quality`
+//!   applies to JPEG and AVIF; WebP is always encoded losslessly.
+//! - `image_srcset(path, format)`: variants at the `[images]` widths, ready
+//!   for `<img src srcset width height>`.
+//! - `get_image_metadata(path)`: an image's width, height and format.
+//!
+//! Filters:
+//!
+//! - `markdown(inline=false)`: renders Markdown to sanitized HTML.
+//! - `slugify`: slugifies with the site's `slugify` style.
+//! - `absolute_url`: joins a site-relative URL onto `base_url`.
+//!
+//! Dates are formatted with Tera's built-in `date` filter, which accepts the
+//! `YYYY-MM-DD` dates used in front matter.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tera::{Error, Result, Tera, Value};
+
+use crate::{
+    config::SiteConfig,
+    context::SiteContext,
+    data::load_data_file,
+    images::{Format, ImageProcessor, Resize},
+    render::render_markdown,
+    routing::{SlugStyle, absolute_url, slugify_with},
+    taxonomy::PageSummary,
+};
+
+pub fn register(tera: &mut Tera, cfg: &SiteConfig, site: &SiteContext, pages: &[PageSummary]) {
+    let base_url: Arc<str> = Arc::from(cfg.base_url.as_str());
+    let pages: Arc<HashMap<String, PageSummary>> =
+        Arc::new(pages.iter().map(|p| (p.path.clone(), p.clone())).collect());
+    let global = |key: &str| Arc::new(site.get(key).cloned().unwrap_or_default());
+    let sections = global("sections");
+    let taxonomies = global("taxonomies");
+    let assets = global("assets");
+
+    tera.register_function("get_url", {
+        let (pages, base_url) = (pages.clone(), base_url.clone());
+        move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path", "get_url")?;
+            if path.ends_with(".md") {
+                let page = find_page(&pages, path)?;
+                return Ok(Value::String(absolute_url(&base_url, &page.url)));
+            }
+            Ok(Value::String(absolute_url(&base_url, path)))
+        }
+    });
+    tera.register_function("get_page", move |args: &HashMap<String, Value>| {
+        let page = find_page(&pages, str_arg(args, "path", "get_page")?)?;
+        tera::to_value(page).map_err(Error::from)
+    });
+    tera.register_function("get_section", move |args: &HashMap<String, Value>| {
+        let path = str_arg(args, "path", "get_section")?;
+        let key = path
+            .trim_start_matches("@/")
+            .trim_end_matches("_index.md")
+            .trim_end_matches("index.md")
+            .trim_matches('/');
+        sections
+            .get(key)
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("get_section: no section `{path}`")))
+    });
+    tera.register_function("get_taxonomy", move |args: &HashMap<String, Value>| {
+        let kind = str_arg(args, "kind", "get_taxonomy")?;
+        taxonomies
+            .get(kind)
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("get_taxonomy: no taxonomy `{kind}`")))
+    });
+    tera.register_function("asset_url", {
+        let assets = assets.clone();
+        move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path", "asset_url")?;
+            asset(&assets, path, "asset_url")?
+                .get("url")
+                .cloned()
+                .ok_or_else(|| Error::msg(format!("asset_url: no asset `{path}`")))
+        }
+    });
+    tera.register_function("asset_integrity", move |args: &HashMap<String, Value>| {
+        let path = str_arg(args, "path", "asset_integrity")?;
+        asset(&assets, path, "asset_integrity")?
+            .get("integrity")
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("asset_integrity: `{path}` is not hashed")))
+    });
+    let images = Arc::new(ImageProcessor::new(cfg));
+    tera.register_function("resize_image", {
+        let images = images.clone();
+        move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path", "resize_image")?;
+            let req = Resize {
+                width: u32_arg(args, "width", "resize_image")?,
+                height: u32_arg(args, "height", "resize_image")?,
+                format: format_arg(args, "resize_image")?,
+                quality: u32_arg(args, "quality", "resize_image")?.map(|q| q.min(100) as u8),
+            };
+            let image = images
+                .resize(path, req)
+                .map_err(|e| Error::msg(format!("resize_image: {e}")))?;
+            tera::to_value(image).map_err(Error::from)
+        }
+    });
+    tera.register_function("image_srcset", {
+        let images = images.clone();
+        move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path", "image_srcset")?;
+            let set = images
+                .srcset(path, format_arg(args, "image_srcset")?)
+                .map_err(|e| Error::msg(format!("image_srcset: {e}")))?;
+            tera::to_value(set).map_err(Error::from)
+        }
+    });
+    tera.register_function(
+        "get_image_metadata",
+        move |args: &HashMap<String, Value>| {
+            let path = str_arg(args, "path", "get_image_metadata")?;
+            let meta = images
+                .metadata(path)
+                .map_err(|e| Error::msg(format!("get_image_metadata: {e}")))?;
+            tera::to_value(meta).map_err(Error::from)
+        },
+    );
+    let src_dir = cfg.src_dir.clone();
+    tera.register_function("load_data", move |args: &HashMap<String, Value>| {
+        let path = str_arg(args, "path", "load_data")?;
+        let file = resolve_in(&src_dir, path)
+            .ok_or_else(|| Error::msg(format!("load_data: `{path}` is not inside the site")))?;
+        load_data_file(&file).map_err(|e| Error::msg(format!("load_data: {e}")))
+    });
+
+    let style = cfg.slugify;
+    tera.register_filter(
+        "markdown",
+        move |value: &Value, args: &HashMap<String, Value>| {
+            let text = value
+                .as_str()
+                .ok_or_else(|| Error::msg("markdown: expected a string"))?;
+            let inline = args.get("inline").and_then(Value::as_bool).unwrap_or(false);
+            Ok(Value::String(markdown(text, style, inline)))
+        },
+    );
+    tera.register_filter(
+        "slugify",
+        move |value: &Value, _: &HashMap<String, Value>| {
+            let text = value
+                .as_str()
+                .ok_or_else(|| Error::msg("slugify: expected a string"))?;
+            Ok(Value::String(slugify_with(text, style)))
+        },
+    );
+    tera.register_filter(
+        "absolute_url",
+        move |value: &Value, _: &HashMap<String, Value>| {
+            let url = value
+                .as_str()
+                .ok_or_else(|| Error::msg("absolute_url: expected a string"))?;
+            Ok(Value::String(absolute_url(&base_url, url)))
+        },
+    );
+}
+
+fn str_arg<'a>(args: &'a HashMap<String, Value>, name: &str, func: &str) -> Result<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::msg(format!("{func}: missing string argument `{name}`")))
+}
+
+fn u32_arg(args: &HashMap<String, Value>, name: &str, func: &str) -> Result<Option<u32>> {
+    match args.get(name) {
+        None => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| Error::msg(format!("{func}: `{name}` must be a positive integer"))),
+    }
+}
+
+/// The optional `format` argument; `auto` keeps the source format.
+fn format_arg(args: &HashMap<String, Value>, func: &str) -> Result<Option<Format>> {
+    match args.get("format").and_then(Value::as_str) {
+        None | Some("auto") => Ok(None),
+        Some(f) => Format::from_ext(f)
+            .map(Some)
+            .ok_or_else(|| Error::msg(format!("{func}: unknown format `{f}`"))),
+    }
+}
+
+fn find_page<'a>(pages: &'a HashMap<String, PageSummary>, path: &str) -> Result<&'a PageSummary> {
+    let key = path.trim_start_matches("@/").trim_start_matches('/');
+    pages
+        .get(key)
+        .ok_or_else(|| Error::msg(format!("no page at `{path}`")))
+}
+
+fn asset<'a>(assets: &'a Value, path: &str, func: &str) -> Result<&'a Value> {
+    assets
+        .get(path.trim_start_matches('/'))
+        .ok_or_else(|| Error::msg(format!("{func}: no asset `{path}`")))
+}
+
+/// `path` under `root`, as long as it does not escape it.
+fn resolve_in(root: &Path, path: &str) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let file = root
+        .join(path.trim_start_matches('/'))
+        .canonicalize()
+        .ok()?;
+    file.starts_with(&root).then_some(file)
+}
+
+fn markdown(text: &str, style: SlugStyle, inline: bool) -> String {
+    let html = render_markdown(text, style);
+    let trimmed = html.trim_end();
+    // A lone paragraph loses its `<p>` so the result can sit inside other text.
+    match trimmed
+        .strip_prefix("<p>")
+        .and_then(|s| s.strip_suffix("</p>"))
+    {
+        Some(inner) if inline && !inner.contains("<p>") => inner.to_string(),
+        _ => html,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assets::AssetManifest, config::AssetsConfig, context::Environment};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn functions_and_filters_see_the_site() {
+        let tmp = tempdir().unwrap();
+        fs::write(tmp.path().join("authors.json"), r#"{"ada": "Ada"}"#).unwrap();
+        let cfg = SiteConfig {
+            base_url: "https://example.com/".into(),
+            src_dir: tmp.path().to_path_buf(),
+            ..Default::default()
+        };
+        let pages = vec![PageSummary {
+            title: "First".into(),
+            url: "/posts/first/".into(),
+            path: "posts/first.md".into(),
+            date: Some("2024-03-05".into()),
+            tags: vec!["Rust".into()],
+            ..Default::default()
+        }];
+        let mut assets = vec![(tmp.path().join("app.css"), tmp.path().join("out/app.css"))];
+        fs::write(&assets[0].0, "body{}").unwrap();
+        let assets_cfg = AssetsConfig {
+            fingerprint: true,
+            ..Default::default()
+        };
+        let manifest =
+            AssetManifest::build(&assets_cfg, &tmp.path().join("out"), &mut assets).unwrap();
+        let site = SiteContext::new(
+            &cfg,
+            Environment::Production,
+            &pages,
+            Default::default(),
+            &manifest,
+        );
+        let mut tera = Tera::default();
+        register(&mut tera, &cfg, &site, &pages);
+
+        let render = |tpl: &str| {
+            let mut tera = tera.clone();
+            tera.add_raw_template("t", tpl).unwrap();
+            tera.render("t", &site.context()).unwrap()
+        };
+        assert_eq!(
+            render(r#"{{ get_url(path="posts/first.md") }}"#),
+            "https://example.com/posts/first/"
+        );
+        assert_eq!(
+            render(r#"{% set p = get_page(path="posts/first.md") %}{{ p.title }}"#),
+            "First"
+        );
+        assert_eq!(
+            render(r#"{% set s = get_section(path="posts") %}{{ s.pages | length }}"#),
+            "1"
+        );
+        assert_eq!(
+            render(r#"{% set t = get_taxonomy(kind="tags") %}{{ t.terms.0.name }}"#),
+            "Rust"
+        );
+        assert_eq!(
+            render(r#"{% set d = load_data(path="authors.json") %}{{ d.ada }}"#),
+            "Ada"
+        );
+        assert_eq!(
+            render(r#"{{ "*hi*" | markdown(inline=true) | safe }}"#),
+            "<em>hi</em>"
+        );
+        assert_eq!(render(r#"{{ "Hello World" | slugify }}"#), "hello-world");
+        assert_eq!(
+            render(r#"{{ "/feed.xml" | absolute_url }}"#),
+            "https://example.com/feed.xml"
+        );
+        assert_eq!(
+            render(r#"{{ "2024-03-05" | date(format="%d.%m.%Y") }}"#),
+            "05.03.2024"
+        );
+
+        assert_eq!(
+            render(r#"{{ asset_url(path="/app.css") }}"#),
+            manifest.entries["app.css"].url
+        );
+        assert!(render(r#"{{ asset_integrity(path="app.css") }}"#).starts_with("sha384-"));
+
+        let mut bad = tera.clone();
+        bad.add_raw_template("t", r#"{{ asset_url(path="missing.css") }}"#)
+            .unwrap();
+        assert!(bad.render("t", &site.context()).is_err());
+
+        bad.add_raw_template("t", r#"{{ load_data(path="../x.json") }}"#)
+            .unwrap();
+        assert!(bad.render("t", &site.context()).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Paths the build never looks at: hidden files, plus whatever `.ssgignore`
+//! and the `ignore` list in `site.toml` name, both with gitignore semantics.
+//!
+//! Hidden files are excluded through an implicit `.*` rule ahead of every
+//! other pattern, so a negation such as `!.well-known/` brings them back.
+
+use std::{
+    fs, io,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Ignore file read from the root of the source tree.
+pub const IGNORE_FILE: &str = ".ssgignore";
+
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    root: PathBuf,
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    /// Rules for the tree under `root`: hidden files, then `root/.ssgignore`
+    /// if there is one, then `patterns`. Later patterns win.
+    pub fn load(root: &Path, patterns: &[String]) -> io::Result<Self> {
+        let invalid = |e: ignore::Error| io::Error::new(ErrorKind::InvalidData, e.to_string());
+        let mut builder = GitignoreBuilder::new(root);
+        builder.add_line(None, ".*").map_err(invalid)?;
+
+        let file = root.join(IGNORE_FILE);
+        match fs::read_to_string(&file) {
+            Ok(text) => {
+                for line in text.lines() {
+                    builder
+                        .add_line(Some(file.clone()), line)
+                        .map_err(|e| invalid_in(&file, e))?;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        for line in patterns {
+            builder.add_line(None, line).map_err(invalid)?;
+        }
+
+        let matcher = builder.build().map_err(invalid)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            matcher,
+        })
+    }
+
+    /// Whether `path` or one of its parents is ignored. Paths outside the
+    /// root never are; the root itself may sit in a hidden directory.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if rel.as_os_str().is_empty() {
+            return false;
+        }
+        self.matcher
+            .matched_path_or_any_parents(rel, is_dir)
+            .is_ignore()
+    }
+}
+
+fn invalid_in(file: &Path, e: ignore::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{}: {e}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn hidden_files_are_ignored_unless_negated() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        fs::write(
+            root.join(IGNORE_FILE),
+            "# comment\nnode_modules/\n!.well-known/\n",
+        )?;
+        let rules = IgnoreRules::load(root, &["drafts/".into(), "*.bak".into()])?;
+
+        assert!(rules.is_ignored(&root.join(".DS_Store"), false));
+        assert!(rules.is_ignored(&root.join("posts/.hidden.md"), false));
+        assert!(rules.is_ignored(&root.join(".git/config"), false));
+        assert!(!rules.is_ignored(&root.join(".well-known/security.txt"), false));
+        assert!(rules.is_ignored(&root.join("node_modules"), true));
+        assert!(rules.is_ignored(&root.join("node_modules/x/index.js"), false));
+        assert!(rules.is_ignored(&root.join("drafts/a.md"), false));
+        assert!(rules.is_ignored(&root.join("posts/old.bak"), false));
+        assert!(!rules.is_ignored(&root.join("posts/a.md"), false));
+        assert!(!rules.is_ignored(root, true));
+        assert!(!rules.is_ignored(Path::new("/elsewhere/.x"), false));
+        Ok(())
+    }
+
+    #[test]
+    fn later_patterns_override_the_ignore_file() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        fs::write(root.join(IGNORE_FILE), "*.txt\n")?;
+        let rules = IgnoreRules::load(root, &["!robots.txt".into()])?;
+
+        assert!(rules.is_ignored(&root.join("notes.txt"), false));
+        assert!(!rules.is_ignored(&root.join("robots.txt"), false));
+        Ok(())
+    }
+}
@@ -0,0 +1,578 @@
+//! Image processing for templates: resizing, format conversion, `srcset`
+//! variants and dimensions.
+//!
+//! Processed images are written to `processed_images/` in the output, named
+//! by a hash of the source content and the parameters. A file that is already
+//! there is reused, so unchanged images are only processed once. Renders run
+//! under [`track`] to learn which files they use, and files no output uses
+//! any more are removed by [`prune`].
+
+use image::{
+    DynamicImage, ImageEncoder, ImageReader,
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, OnceLock},
+};
+
+use crate::{
+    cache,
+    config::{ImagesConfig, SiteConfig},
+};
+
+/// Output directory for processed images, relative to the output root.
+pub const OUTPUT_DIR: &str = "processed_images";
+
+thread_local! {
+    /// Files requested by the render running on this thread, under [`track`].
+    static USED: RefCell<Option<BTreeSet<String>>> = const { RefCell::new(None) };
+}
+
+/// Runs `render`, also returning the names of the processed images it asked
+/// for. Template functions run on the rendering thread, so renders running in
+/// parallel on other threads are not counted.
+pub fn track<T>(render: impl FnOnce() -> T) -> (T, BTreeSet<String>) {
+    let outer = USED.with(|used| used.replace(Some(BTreeSet::new())));
+    let out = render();
+    let used = USED.with(|used| used.replace(outer)).unwrap_or_default();
+    (out, used)
+}
+
+/// Removes the files in the processed images directory under `out_root` that
+/// are not in `keep`, and the directory itself once it is empty.
+pub fn prune(out_root: &Path, keep: &BTreeSet<String>) -> io::Result<()> {
+    let dir = out_root.join(OUTPUT_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut left = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if keep.contains(&name) || !entry.file_type()?.is_file() {
+            left += 1;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    if left == 0 {
+        fs::remove_dir(&dir)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl Format {
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+}
+
+/// Whether `path` is an image the processor can read.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| Format::from_ext(e).is_some())
+}
+
+/// What `resize_image` asks for. Without `width` and `height` the size is
+/// kept; with both, the image is fitted inside the box. Images are never
+/// upscaled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Resize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Defaults to the source format.
+    pub format: Option<Format>,
+    /// Defaults to `[images] quality`. Ignored for PNG and WebP, which the
+    /// `image` crate only encodes losslessly.
+    pub quality: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessedImage {
+    /// Site-relative URL of the processed file.
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageMeta {
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+}
+
+/// Everything an `<img srcset>` needs: `src` and the dimensions are those
+/// of the widest variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SrcSet {
+    pub src: String,
+    pub srcset: String,
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ProcessedImage>,
+}
+
+/// Source file, output width and height, format and quality.
+type Job = (PathBuf, u32, u32, Format, u8);
+/// The result of a job once it is done, and a signal for those waiting.
+type Slot = Arc<(Mutex<Option<Result<ProcessedImage, String>>>, Condvar)>;
+
+/// Threads images are processed on. Renders run on the global rayon pool and
+/// wait for their images without taking on other work, so the processing,
+/// which may use rayon itself, must not need a thread of that pool.
+fn pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .thread_name(|i| format!("images-{i}"))
+            .build()
+            .expect("cannot start image threads")
+    })
+}
+
+/// Processes images for one build. Shared by every render, so identical
+/// requests from different pages are only processed once.
+pub struct ImageProcessor {
+    roots: Vec<PathBuf>,
+    out_dir: PathBuf,
+    cfg: ImagesConfig,
+    done: Mutex<HashMap<Job, Slot>>,
+}
+
+impl ImageProcessor {
+    /// Images are looked up in the site root, then in the theme's `static/`.
+    pub fn new(cfg: &SiteConfig) -> Self {
+        let mut roots = vec![cfg.src_dir.clone()];
+        roots.extend(cfg.theme_dir().map(|d| d.join("static")));
+        Self {
+            roots,
+            out_dir: cfg.out_dir.join(OUTPUT_DIR),
+            cfg: cfg.images.clone(),
+            done: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The source file for a site-relative `path`, which may not escape the
+    /// directories images are looked up in.
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let rel = path.trim_start_matches('/');
+        self.roots
+            .iter()
+            .find_map(|root| {
+                let root = root.canonicalize().ok()?;
+                let file = root.join(rel).canonicalize().ok()?;
+                (file.starts_with(&root) && file.is_file()).then_some(file)
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no image `{path}`")))
+    }
+
+    pub fn metadata(&self, path: &str) -> io::Result<ImageMeta> {
+        let file = self.resolve(path)?;
+        let format = source_format(&file)?;
+        let (width, height) = dimensions(&file)?;
+        Ok(ImageMeta {
+            width,
+            height,
+            format,
+        })
+    }
+
+    pub fn resize(&self, path: &str, req: Resize) -> io::Result<ProcessedImage> {
+        let file = self.resolve(path)?;
+        let format = req.format.map_or_else(|| source_format(&file), Ok)?;
+        let (src_w, src_h) = dimensions(&file)?;
+        let (width, height) = target_size((src_w, src_h), req.width, req.height);
+        let quality = match format {
+            // Lossless either way, so one file serves every quality.
+            Format::Png | Format::Webp => 100,
+            Format::Jpeg | Format::Avif => req.quality.unwrap_or(self.cfg.quality).clamp(1, 100),
+        };
+
+        let job = (file, width, height, format, quality);
+        let (slot, first) = {
+            let mut done = self.done.lock().unwrap_or_else(|e| e.into_inner());
+            match done.get(&job) {
+                Some(slot) => (slot.clone(), false),
+                None => {
+                    let slot = Slot::default();
+                    done.insert(job.clone(), slot.clone());
+                    (slot, true)
+                }
+            }
+        };
+        // The first request starts the job off the rendering threads, and
+        // every request waits for it.
+        if first {
+            let (slot, out_dir) = (slot.clone(), self.out_dir.clone());
+            pool().spawn(move || {
+                let result = process(&out_dir, &job).map_err(|e| e.to_string());
+                let (state, ready) = &*slot;
+                *state.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                ready.notify_all();
+            });
+        }
+        let (state, ready) = &*slot;
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let result = loop {
+            match &*state {
+                Some(result) => break result.clone(),
+                None => state = ready.wait(state).unwrap_or_else(|e| e.into_inner()),
+            }
+        };
+        let image = result.map_err(io::Error::other)?;
+        USED.with(|used| {
+            if let Some(used) = used.borrow_mut().as_mut()
+                && let Some(name) = image.url.rsplit('/').next()
+            {
+                used.insert(name.to_string());
+            }
+        });
+        Ok(image)
+    }
+
+    /// One variant per configured width narrower than the source, plus the
+    /// source width itself.
+    pub fn srcset(&self, path: &str, format: Option<Format>) -> io::Result<SrcSet> {
+        let (src_w, _) = dimensions(&self.resolve(path)?)?;
+        let mut widths: Vec<u32> = self
+            .cfg
+            .widths
+            .iter()
+            .copied()
+            .filter(|&w| w > 0 && w < src_w)
+            .chain([src_w])
+            .collect();
+        widths.sort_unstable();
+        widths.dedup();
+
+        let variants = widths
+            .into_iter()
+            .map(|w| {
+                self.resize(
+                    path,
+                    Resize {
+                        width: Some(w),
+                        format,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let widest = variants
+            .last()
+            .cloned()
+            .ok_or_else(|| io::Error::other("empty image"))?;
+        let srcset = variants
+            .iter()
+            .map(|v| format!("{} {}w", v.url, v.width))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(SrcSet {
+            src: widest.url,
+            srcset,
+            width: widest.width,
+            height: widest.height,
+            variants,
+        })
+    }
+}
+
+fn process(out_dir: &Path, job: &Job) -> io::Result<ProcessedImage> {
+    let &(ref file, width, height, format, quality) = job;
+    let key = format!(
+        "{}|{width}x{height}|{}|{quality}",
+        cache::file_hash(file)?,
+        format.ext()
+    );
+    let stem = file
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = format!(
+        "{stem}.{}.{}",
+        &cache::hash_bytes(key.as_bytes())[..16],
+        format.ext()
+    );
+    let out = out_dir.join(&name);
+    if !out.is_file() {
+        let img = ImageReader::open(file)?
+            .with_guessed_format()?
+            .decode()
+            .map_err(|e| invalid(file, e))?;
+        let img = if (width, height) == (img.width(), img.height()) {
+            img
+        } else {
+            img.resize_exact(width, height, FilterType::Lanczos3)
+        };
+        fs::create_dir_all(out_dir)?;
+        // Written under a temporary name so a half-written file is never
+        // mistaken for a finished one.
+        let tmp = out.with_extension(format!("{}.tmp", format.ext()));
+        encode(&img, format, quality, &tmp).map_err(|e| invalid(file, e))?;
+        fs::rename(&tmp, &out)?;
+    }
+    Ok(ProcessedImage {
+        url: format!("/{OUTPUT_DIR}/{name}"),
+        width,
+        height,
+        format,
+    })
+}
+
+/// Fingerprint of the images among `assets` (by path, size and modification
+/// time), for invalidating pages whose templates process images.
+pub fn stamp(assets: &[(PathBuf, PathBuf)]) -> String {
+    let mut stamp = String::new();
+    for (src, _) in assets.iter().filter(|(src, _)| is_image(src)) {
+        if let Ok(meta) = fs::metadata(src) {
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+            stamp.push_str(&format!("{}|{}|{mtime:?};", src.display(), meta.len()));
+        }
+    }
+    cache::hash_bytes(stamp.as_bytes())
+}
+
+fn encode(img: &DynamicImage, format: Format, quality: u8, path: &Path) -> image::ImageResult<()> {
+    let w = BufWriter::new(fs::File::create(path)?);
+    match format {
+        // JPEG has no alpha channel.
+        Format::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(w, quality)),
+        Format::Png => img.write_with_encoder(PngEncoder::new(w)),
+        Format::Webp => {
+            let rgba = img.to_rgba8();
+            WebPEncoder::new_lossless(w).write_image(
+                &rgba,
+                rgba.width(),
+                rgba.height(),
+                image::ExtendedColorType::Rgba8,
+            )
+        }
+        Format::Avif => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(AvifEncoder::new_with_speed_quality(w, 8, quality)),
+    }
+}
+
+/// Size of the output: scaled to the requested width or height, or fitted
+/// into both, keeping the aspect ratio and never exceeding the source.
+fn target_size((w, h): (u32, u32), width: Option<u32>, height: Option<u32>) -> (u32, u32) {
+    let scale = match (width, height) {
+        (Some(tw), Some(th)) => (tw as f64 / w as f64).min(th as f64 / h as f64),
+        (Some(tw), None) => tw as f64 / w as f64,
+        (None, Some(th)) => th as f64 / h as f64,
+        (None, None) => 1.0,
+    }
+    .min(1.0);
+    let scaled = |n: u32| ((n as f64 * scale).round() as u32).max(1);
+    (scaled(w), scaled(h))
+}
+
+fn dimensions(file: &Path) -> io::Result<(u32, u32)> {
+    ImageReader::open(file)?
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(|e| invalid(file, e))
+}
+
+fn source_format(file: &Path) -> io::Result<Format> {
+    file.extension()
+        .and_then(|e| e.to_str())
+        .and_then(Format::from_ext)
+        .ok_or_else(|| invalid(file, "not a JPEG, PNG, WebP or AVIF image"))
+}
+
+fn invalid(file: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {e}", file.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    fn site(root: &Path) -> SiteConfig {
+        let src = root.join("src");
+        fs::create_dir_all(src.join("img")).unwrap();
+        RgbImage::from_pixel(40, 20, Rgb([200, 10, 10]))
+            .save(src.join("img/photo.png"))
+            .unwrap();
+        SiteConfig {
+            src_dir: src,
+            out_dir: root.join("out"),
+            images: ImagesConfig {
+                widths: vec![10, 20, 80],
+                quality: 70,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn target_size_keeps_aspect_and_never_upscales() {
+        assert_eq!(target_size((40, 20), Some(10), None), (10, 5));
+        assert_eq!(target_size((40, 20), None, Some(4)), (8, 4));
+        assert_eq!(target_size((40, 20), Some(30), Some(5)), (10, 5));
+        assert_eq!(target_size((40, 20), Some(400), None), (40, 20));
+        assert_eq!(target_size((40, 20), None, None), (40, 20));
+    }
+
+    #[test]
+    fn resizes_converts_and_reuses_results() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path());
+        let images = ImageProcessor::new(&cfg);
+
+        let meta = images.metadata("/img/photo.png").unwrap();
+        assert_eq!(
+            (meta.width, meta.height, meta.format),
+            (40, 20, Format::Png)
+        );
+
+        let req = Resize {
+            width: Some(10),
+            format: Some(Format::Webp),
+            ..Default::default()
+        };
+        let small = images.resize("img/photo.png", req).unwrap();
+        assert_eq!((small.width, small.height), (10, 5));
+        assert!(small.url.starts_with("/processed_images/photo.") && small.url.ends_with(".webp"));
+        let out = cfg.out_dir.join(small.url.trim_start_matches('/'));
+        assert_eq!(image::image_dimensions(&out).unwrap(), (10, 5));
+
+        // A fresh processor finds the file from the previous build.
+        let stamp = fs::metadata(&out).unwrap().modified().unwrap();
+        let again = ImageProcessor::new(&cfg)
+            .resize("img/photo.png", req)
+            .unwrap();
+        assert_eq!(again, small);
+        assert_eq!(fs::metadata(&out).unwrap().modified().unwrap(), stamp);
+
+        // WebP is lossless, so quality makes no difference.
+        let lossless = Resize {
+            quality: Some(10),
+            ..req
+        };
+        assert_eq!(images.resize("img/photo.png", lossless).unwrap(), small);
+
+        let avif = Resize {
+            format: Some(Format::Avif),
+            ..req
+        };
+        let avif = images.resize("img/photo.png", avif).unwrap();
+        assert!(cfg.out_dir.join(avif.url.trim_start_matches('/')).is_file());
+
+        assert!(images.resize("../outside.png", req).is_err());
+    }
+
+    #[test]
+    fn srcset_lists_every_narrower_width() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path());
+        let set = ImageProcessor::new(&cfg)
+            .srcset("img/photo.png", Some(Format::Jpeg))
+            .unwrap();
+        let widths: Vec<u32> = set.variants.iter().map(|v| v.width).collect();
+        assert_eq!(widths, [10, 20, 40]);
+        assert_eq!((set.width, set.height), (40, 20));
+        assert_eq!(set.src, set.variants[2].url);
+        assert_eq!(
+            set.srcset,
+            format!(
+                "{} 10w, {} 20w, {} 40w",
+                set.variants[0].url, set.variants[1].url, set.src
+            )
+        );
+    }
+
+    #[test]
+    fn parallel_renders_share_one_job() {
+        use rayon::prelude::*;
+
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path());
+        let images = ImageProcessor::new(&cfg);
+        let req = Resize {
+            width: Some(20),
+            format: Some(Format::Avif),
+            ..Default::default()
+        };
+        let urls: BTreeSet<String> = (0..64)
+            .into_par_iter()
+            .map(|_| images.resize("img/photo.png", req).unwrap().url)
+            .collect();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(
+            fs::read_dir(cfg.out_dir.join(OUTPUT_DIR)).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn prune_keeps_only_tracked_images() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path());
+        let images = ImageProcessor::new(&cfg);
+        let resize = |width| {
+            let req = Resize {
+                width: Some(width),
+                ..Default::default()
+            };
+            images.resize("img/photo.png", req).unwrap()
+        };
+        let old = resize(10);
+        let (new, used) = track(|| resize(20));
+        let name = new.url.rsplit('/').next().unwrap().to_string();
+        assert_eq!(used, BTreeSet::from([name]));
+
+        prune(&cfg.out_dir, &used).unwrap();
+        assert!(cfg.out_dir.join(&new.url[1..]).is_file());
+        assert!(!cfg.out_dir.join(&old.url[1..]).exists());
+
+        prune(&cfg.out_dir, &BTreeSet::new()).unwrap();
+        assert!(!cfg.out_dir.join(OUTPUT_DIR).exists());
+    }
+}
@@ -1,39 +1,93 @@
+pub mod assets;
+pub mod bundles;
 pub mod cache;
 pub mod cli;
 pub mod config;
 pub mod content;
+pub mod context;
+pub mod data;
+pub mod deps;
 pub mod devserver;
+pub mod functions;
+pub mod ignores;
+pub mod images;
+pub mod minify;
 pub mod paginate;
+pub mod pipeline;
+pub mod redirects;
 pub mod render;
 pub mod routing;
+pub mod sections;
 pub mod taxonomy;
 pub mod templates;
 
 use crate::{
-    cache::BuildCache,
+    assets::AssetManifest,
+    cache::{BuildCache, PageEntry},
     cli::{Action, Args},
-    config::{SiteConfig, load_config},
-    content::{PageMeta, collect_markdown_files, load_document},
-    routing::{copy_static_assets, out_path_for},
-    taxonomy::{PageSummary, summarize, write_tag_pages},
-    templates::Templates,
+    config::{SiteConfig, TaxonomyConfig, load_config},
+    content::{
+        Document, PageMeta, collect_markdown_files, expand_dirs, is_section_index, parse_document,
+    },
+    context::{Environment, SiteContext},
+    data::load_data_dir,
+    deps::{DepGraph, PageDeps},
+    ignores::IgnoreRules,
+    redirects::{Redirect, write_redirect_page, write_redirects},
+    routing::{
+        OutputClaims, copy_static_assets, out_path_for, remove_unclaimed, static_assets, write_html,
+    },
+    sections::{SectionListing, SectionPages},
+    taxonomy::{PageSummary, summarize, taxonomy_output_paths, write_taxonomy_pages},
+    templates::{Templates, template_for},
 };
 use rayon::prelude::*;
 use std::{
-    fs,
-    path::Path,
+    collections::{BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
 };
 
-pub fn run(args: Args) {
+/// Runs a CLI action. Fails when the build does, or when any output could
+/// not be built; the causes have already been reported on stderr by then.
+pub fn run(args: Args) -> io::Result<()> {
     match args.action {
-        Action::Build { src, out } => build(&src, &out),
+        Action::Build { src, out } => match build(&src, &out, Environment::Production, None) {
+            None => return Err(io::Error::other("build failed")),
+            Some(b) if b.failed > 0 => {
+                return Err(io::Error::other(format!(
+                    "build failed ({} failed)",
+                    b.failed
+                )));
+            }
+            Some(_) => {}
+        },
         Action::Serve { src, out } => devserver::serve(&src, &out),
         Action::Clean { out } => clean(&out),
     }
+    Ok(())
+}
+
+/// A finished build.
+struct Built {
+    graph: DepGraph,
+    /// Outputs that were left out because building them failed.
+    failed: usize,
 }
 
-fn build(src: &Path, out: &Path) {
+/// Builds `src` into `out` and returns the dependency graph of the result.
+/// `None` means nothing was built.
+///
+/// `changes` carries the previous build's graph and the source paths that
+/// changed since; only outputs depending on those paths are re-rendered.
+/// Without it, pages are skipped based on the on-disk build cache.
+fn build(
+    src: &Path,
+    out: &Path,
+    env: Environment,
+    changes: Option<(&DepGraph, &[PathBuf])>,
+) -> Option<Built> {
     let cfg: SiteConfig = match load_config(src) {
         Ok(mut c) => {
             c.src_dir = src.to_path_buf();
@@ -42,32 +96,50 @@ fn build(src: &Path, out: &Path) {
         }
         Err(e) => {
             eprintln!("config: {e}");
-            return;
+            return None;
+        }
+    };
+    let ignores = match IgnoreRules::load(&cfg.src_dir, &cfg.ignore) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("ignore: {e}");
+            return None;
         }
     };
 
-    let tpl_dir = cfg.src_dir.join("templates");
-    let templates = match Templates::load_from(&tpl_dir) {
+    let tpl_dirs = cfg.template_dirs();
+    let theme_dir = cfg.theme_dir();
+    let mut templates = match Templates::load_layers(&tpl_dirs) {
         Ok(t) => t,
         Err(e) => {
             eprintln!("templates: {e}");
-            return;
+            return None;
         }
     };
 
-    let mut summaries: Vec<PageSummary> = Vec::new();
-    let md_files = collect_markdown_files(&cfg.src_dir);
+    let mut graph = DepGraph::scan_templates(&tpl_dirs);
+    let affected = changes
+        .and_then(|(prev, paths)| prev.affected(&cfg.src_dir, &expand_dirs(paths, &ignores)));
 
-    let mut cache_prev: BuildCache = cache::load(&cfg.out_dir);
-    let tpl_hash = cache::templates_hash(&tpl_dir).unwrap_or_default();
-    let templates_changed = cache_prev.templates_hash != tpl_hash;
+    let mut summaries: Vec<PageSummary> = Vec::new();
+    let md_files = collect_markdown_files(&cfg.src_dir, &ignores);
 
+    let cfg_hash = cache::config_hash(&cfg, env);
+    let cache_prev: BuildCache = cache::load(&cfg.out_dir, &cfg_hash);
+    let data = match load_data_dir(&cfg.src_dir.join("data")) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("data: {e}");
+            return None;
+        }
+    };
     let built = AtomicUsize::new(0);
     let skipped = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
 
-    let prev_map = cache_prev.pages.clone();
-
-    let results: Vec<PageSummary> = md_files
+    // Resolve every page's metadata and output path before writing anything,
+    // so conflicting routes are caught up front.
+    let plans: Vec<PagePlan> = md_files
         .par_iter()
         .filter_map(|md| {
             let rel = md
@@ -75,82 +147,517 @@ fn build(src: &Path, out: &Path) {
                 .unwrap_or(md)
                 .to_string_lossy()
                 .to_string();
-            let file_hash = match cache::file_hash(md) {
-                Ok(h) => h,
+            let text = match fs::read_to_string(md) {
+                Ok(t) => t,
                 Err(e) => {
-                    eprintln!("hash {}: {e}", md.display());
+                    eprintln!("read {}: {e}", md.display());
+                    failed.fetch_add(1, Ordering::Relaxed);
                     return None;
                 }
             };
+            let hash = cache::hash_bytes(text.as_bytes());
 
-            let up_to_date =
-                !templates_changed && prev_map.get(&rel).is_some_and(|h| h == &file_hash);
+            // Unchanged sources reuse the metadata parsed by the previous build.
+            let cached = cache_prev
+                .pages
+                .get(&rel)
+                .filter(|entry| entry.hash == hash);
+            let (meta, out_path, doc) = match cached {
+                Some(entry) => (entry.meta.clone(), cfg.out_dir.join(&entry.output), None),
+                None => {
+                    let doc = match parse_document::<PageMeta>(md, &text) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            eprintln!("load {}: {e}", md.display());
+                            failed.fetch_add(1, Ordering::Relaxed);
+                            return None;
+                        }
+                    };
+                    let out_path = match out_path_for(&cfg, md, &doc) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            eprintln!("route {}: {e}", md.display());
+                            failed.fetch_add(1, Ordering::Relaxed);
+                            return None;
+                        }
+                    };
+                    let meta = doc.front_matter.clone().unwrap_or_default();
+                    (meta, out_path, Some(doc))
+                }
+            };
+            Some(PagePlan {
+                md,
+                rel,
+                text,
+                hash,
+                meta,
+                out_path,
+                doc,
+            })
+        })
+        .collect();
 
-            let doc = match load_document::<PageMeta>(md) {
-                Ok(d) => d,
-                Err(e) => {
-                    eprintln!("load {}: {e}", md.display());
-                    return None;
+    let page_outputs: Vec<(&Path, &Path)> =
+        plans.iter().map(|p| (p.md, p.out_path.as_path())).collect();
+    let bundles = bundles::bundles(&cfg.src_dir, &page_outputs);
+    let mut assets = static_assets(&cfg.src_dir, &cfg.out_dir, theme_dir.as_deref(), &ignores);
+    let bundled = bundles::relocate(&mut assets, &bundles);
+    let processed = match pipeline::process(&cfg, &cache_prev, &mut assets) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("assets: {e}");
+            return None;
+        }
+    };
+    for e in &processed.errors {
+        eprintln!("assets: {e}");
+    }
+    failed.fetch_add(processed.errors.len(), Ordering::Relaxed);
+    let manifest = match AssetManifest::build(&cfg.assets, &cfg.out_dir, &mut assets) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("assets: {e}");
+            return None;
+        }
+    };
+    let planned_summaries: Vec<PageSummary> = plans
+        .iter()
+        .map(|p| p.summary(&cfg.out_dir, &cfg.taxonomies))
+        .collect();
+    let mut tpl_hashes = cache::template_hashes(&tpl_dirs).unwrap_or_default();
+    // Pages whose templates read `data`, look up assets or list other pages
+    // go stale with them, as if the template itself had changed.
+    let data_hash = cache::hash_bytes(&serde_json::to_vec(&data).unwrap_or_default());
+    let build_id = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true);
+    let summaries_hash = {
+        let mut sorted: Vec<&PageSummary> = planned_summaries.iter().collect();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+        cache::hash_bytes(&serde_json::to_vec(&sorted).unwrap_or_default())
+    };
+    let mut assets_hash = serde_json::to_vec(&manifest).unwrap_or_default();
+    assets_hash.extend(images::stamp(&assets).as_bytes());
+    let assets_hash = cache::hash_bytes(&assets_hash);
+    for (name, hash) in tpl_hashes.iter_mut() {
+        let mut inputs = hash.clone();
+        if graph.uses_data(name) {
+            inputs.push_str(&data_hash);
+        }
+        if graph.uses_assets(name) {
+            inputs.push_str(&assets_hash);
+        }
+        if graph.uses_listings(name) {
+            inputs.push_str(&summaries_hash);
+        }
+        match graph.loaded_files(name) {
+            Some(files) => {
+                for file in files {
+                    let hash = cache::file_hash(&cfg.src_dir.join(&file));
+                    inputs.push_str(hash.as_deref().unwrap_or("missing"));
                 }
+            }
+            // Could read anything, so never counts as unchanged.
+            None => inputs.push_str(&build_id),
+        }
+        if inputs != *hash {
+            *hash = cache::hash_bytes(inputs.as_bytes());
+        }
+    }
+
+    // What each bundle page links against: the directory relative links in
+    // its body resolve to, and its files. Other pages keep links as written.
+    let page_files: HashMap<&Path, (Option<String>, Vec<String>)> = plans
+        .iter()
+        .map(|plan| {
+            let files = match bundles.get(plan.md) {
+                Some(bundle) => {
+                    let urls = bundled[plan.md]
+                        .iter()
+                        .filter_map(|dest| {
+                            let key = dest.strip_prefix(&cfg.out_dir).ok()?;
+                            Some(manifest.get(&key.to_string_lossy())?.url.clone())
+                        })
+                        .collect();
+                    (Some(bundle.url(&cfg.out_dir)), urls)
+                }
+                None => (None, Vec::new()),
+            };
+            (plan.md, files)
+        })
+        .collect();
+
+    let site = SiteContext::new(&cfg, env, &planned_summaries, data, &manifest);
+    templates.register_site(&cfg, &site, &planned_summaries);
+    let out_key = |path: &Path| {
+        let rel = path.strip_prefix(&cfg.out_dir).unwrap_or(path);
+        rel.to_string_lossy().into_owned()
+    };
+    let mut claims = OutputClaims::default();
+    let mut redirects: Vec<Redirect> = Vec::new();
+    // The listing pages of each section page, keyed by its source path.
+    let all_sections = context::sections(&planned_summaries);
+    let section_pages: HashMap<String, SectionPages> = plans
+        .iter()
+        .filter(|plan| is_section_index(plan.md))
+        .filter_map(|plan| {
+            let dir = plan.rel.rsplit_once('/').map_or("", |(dir, _)| dir);
+            let section = all_sections.get(dir)?;
+            let split = sections::section_pages(&cfg, &plan.meta, section, &plan.out_path);
+            Some((plan.rel.clone(), split))
+        })
+        .collect();
+    let mut section_outputs = BTreeSet::new();
+    for plan in &plans {
+        claims.claim(plan.out_path.clone(), plan.rel.as_str());
+        if let Some(split) = section_pages.get(&plan.rel) {
+            let extra = split.pages.iter().skip(1).map(|(path, _)| path);
+            for (i, path) in extra.enumerate() {
+                claims.claim(path.clone(), format!("page {} of {}", i + 2, plan.rel));
+                section_outputs.insert(out_key(path));
+            }
+            let redirect = &split.redirect.out_path;
+            claims.claim(redirect.clone(), format!("first page of {}", plan.rel));
+            section_outputs.insert(out_key(redirect));
+        }
+        for alias in &plan.meta.aliases {
+            let redirect = Redirect::new(&cfg.out_dir, alias, &plan.out_path);
+            claims.claim(
+                redirect.out_path.clone(),
+                format!("alias `{alias}` of {}", plan.rel),
+            );
+            redirects.push(redirect);
+        }
+    }
+    let mut taxonomy_outputs = BTreeSet::new();
+    for tax in cfg.taxonomies.iter().filter(|t| t.render) {
+        for (term, path) in taxonomy_output_paths(&cfg, tax, &cfg.out_dir, &planned_summaries) {
+            let source = match term {
+                Some(term) => format!("{} `{term}`", tax.name),
+                None => format!("{} index", tax.name),
             };
+            taxonomy_outputs.insert(out_key(&path));
+            claims.claim(path, source);
+        }
+    }
+    // Sites without a 404 page of their own get the built-in one.
+    let not_found = cfg.out_dir.join("404.html");
+    let own_404 = [
+        not_found.clone(),
+        cfg.out_dir.join("404").join("index.html"),
+    ];
+    let builtin_404 = !plans.iter().any(|p| own_404.contains(&p.out_path))
+        && !assets.iter().any(|(_, dest)| *dest == not_found);
+    if builtin_404 {
+        claims.claim(not_found.clone(), "404 page");
+    }
+    for (asset, dest) in &assets {
+        let source = match processed.sources.get(dest) {
+            Some(label) => label.clone(),
+            None => {
+                let rel = asset.strip_prefix(&cfg.src_dir).unwrap_or(asset);
+                rel.to_string_lossy().into_owned()
+            }
+        };
+        claims.claim(dest.clone(), source);
+    }
+    if cfg.assets.fingerprint {
+        claims.claim(cfg.out_dir.join(assets::MANIFEST_FILE), "asset manifest");
+    }
+    for (path, source) in redirects::rule_files(&cfg) {
+        claims.claim(path, source);
+    }
+    claims.claim_dir(cfg.out_dir.join(images::OUTPUT_DIR), "processed images");
+    claims.claim_dir(cfg.out_dir.join(pipeline::STAGE_DIR), "asset staging");
+    if let Err(e) = claims.check(&cfg.out_dir) {
+        eprintln!("routes: {e}");
+        return None;
+    }
+
+    // Processed images each output uses: what the last build recorded, until
+    // the output is rendered again. Outputs that are gone no longer count.
+    let mut used_images: HashMap<String, BTreeSet<String>> = claims
+        .paths()
+        .filter_map(|path| {
+            let key = out_key(path);
+            let used = cache_prev.images.get(&key)?.clone();
+            Some((key, used))
+        })
+        .collect();
 
-            let out_path = out_path_for(&cfg.src_dir, &cfg.out_dir, md, &doc);
+    // Before rendering, so a page can take over the path of a removed asset.
+    let copied = if affected.as_ref().is_none_or(|a| a.assets) {
+        copy_static_assets(&cfg, &assets, &cache_prev.copied).unwrap_or_else(|e| {
+            eprintln!("assets: {e}");
+            HashMap::new()
+        })
+    } else {
+        cache_prev.copied.clone()
+    };
+    // Term pages of terms no page uses any more, and pages past the last one.
+    if let Err(e) = remove_unclaimed(&cfg.out_dir, &cache_prev.taxonomy_outputs, &claims) {
+        eprintln!("taxonomies: {e}");
+    }
+    if let Err(e) = remove_unclaimed(&cfg.out_dir, &cache_prev.section_outputs, &claims) {
+        eprintln!("sections: {e}");
+    }
+
+    type Rendered = Vec<(PathBuf, BTreeSet<String>)>;
+    let results: Vec<(String, PageEntry, PageSummary, Rendered)> = plans
+        .into_par_iter()
+        .filter_map(|mut plan| {
+            let template = template_for(plan.md, &plan.meta).to_string();
+            let (link_base, page_assets) = &page_files[plan.md];
+            // What the cache says is stale always renders; a watched change
+            // also renders what depends on it and pages the last build
+            // did not know about.
+            let stale = !cache_prev.is_fresh(&plan.rel, &plan.hash, &tpl_hashes, &cfg.out_dir)
+                || cache_prev
+                    .pages
+                    .get(&plan.rel)
+                    .is_some_and(|e| e.assets != *page_assets);
+            // Section pages list the other pages of their directory, which
+            // the cache does not track, so they always render.
+            let needs_render = stale
+                || is_section_index(plan.md)
+                || affected.as_ref().is_some_and(|a| {
+                    a.page_needs_render(&plan.rel, &template)
+                        || changes.is_some_and(|(prev, _)| prev.page(&plan.rel).is_none())
+                });
 
-            if up_to_date {
+            let mut used = Vec::new();
+            if !needs_render {
                 skipped.fetch_add(1, Ordering::Relaxed);
             } else {
-                let html = match templates.render_page(&cfg, &doc) {
-                    Ok(h) => h,
+                let doc = match plan
+                    .doc
+                    .take()
+                    .map_or_else(|| parse_document::<PageMeta>(plan.md, &plan.text), Ok)
+                {
+                    Ok(d) => d,
                     Err(e) => {
-                        eprintln!("render {}: {e}", md.display());
+                        eprintln!("load {}: {e}", plan.md.display());
+                        failed.fetch_add(1, Ordering::Relaxed);
                         return None;
                     }
                 };
-                if let Some(parent) = out_path.parent() {
-                    let _ = fs::create_dir_all(parent);
+                // A section page renders once per listing page.
+                let split = section_pages.get(&plan.rel);
+                let outputs: Vec<(&Path, Option<&SectionListing>)> = match split {
+                    Some(split) => split
+                        .pages
+                        .iter()
+                        .map(|(path, listing)| (path.as_path(), Some(listing)))
+                        .collect(),
+                    None => vec![(plan.out_path.as_path(), None)],
+                };
+                for (out_path, listing) in outputs {
+                    let (html, images) = images::track(|| {
+                        templates.render_page(
+                            &site,
+                            &cfg,
+                            &doc,
+                            link_base.as_deref(),
+                            page_assets,
+                            listing,
+                        )
+                    });
+                    used.push((out_path.to_path_buf(), images));
+                    let html = match html {
+                        Ok(h) => h,
+                        Err(e) => {
+                            eprintln!("render {}: {e}", plan.md.display());
+                            failed.fetch_add(1, Ordering::Relaxed);
+                            return None;
+                        }
+                    };
+                    if let Some(parent) = out_path.parent() {
+                        let _ = fs::create_dir_all(parent);
+                    }
+                    if let Err(e) = write_html(&cfg, out_path, &html) {
+                        eprintln!("write {}: {e}", out_path.display());
+                        failed.fetch_add(1, Ordering::Relaxed);
+                        return None;
+                    }
                 }
-                if let Err(e) = fs::write(&out_path, &html) {
-                    eprintln!("write {}: {e}", out_path.display());
+                if let Some(split) = split
+                    && let Err(e) = write_redirect_page(&cfg, &split.redirect)
+                {
+                    eprintln!("write {}: {e}", split.redirect.out_path.display());
+                    failed.fetch_add(1, Ordering::Relaxed);
                     return None;
                 }
                 built.fetch_add(1, Ordering::Relaxed);
             }
 
-            let meta = doc.front_matter.clone().unwrap_or_default();
-            let title = meta.title.as_deref().unwrap_or("Untitled");
-            Some(summarize(&doc, &cfg.out_dir, &out_path, &meta.tags, title))
+            let summary = plan.summary(&cfg.out_dir, &cfg.taxonomies);
+            let entry = PageEntry {
+                hash: plan.hash,
+                templates: graph.template_closure(&template),
+                output: plan
+                    .out_path
+                    .strip_prefix(&cfg.out_dir)
+                    .unwrap_or(&plan.out_path)
+                    .to_path_buf(),
+                meta: plan.meta,
+                assets: page_assets.clone(),
+            };
+            Some((plan.rel, entry, summary, used))
         })
         .collect();
 
-    summaries.extend(results);
+    let bundle_dirs: HashMap<String, PathBuf> = bundles
+        .iter()
+        .filter_map(|(md, b)| {
+            let rel = md.strip_prefix(&cfg.src_dir).ok()?;
+            let dir = b.dir.strip_prefix(&cfg.src_dir).ok()?;
+            Some((rel.to_string_lossy().to_string(), dir.to_path_buf()))
+        })
+        .collect();
+    let mut new_cache = BuildCache::new(cfg_hash, tpl_hashes);
+    new_cache.assets = processed.built;
+    new_cache.copied = copied;
+    new_cache.taxonomy_outputs = taxonomy_outputs;
+    new_cache.section_outputs = section_outputs;
+    for (rel, entry, summary, rendered) in results {
+        for (path, used) in rendered {
+            used_images.insert(out_key(&path), used);
+        }
+        let terms = cfg
+            .taxonomies
+            .iter()
+            .flat_map(|tax| {
+                entry
+                    .meta
+                    .terms(&tax.name)
+                    .into_iter()
+                    .map(move |t| (tax.name.clone(), tax.term_key(&t)))
+            })
+            .collect();
+        let deps = PageDeps {
+            template: template_for(Path::new(&rel), &entry.meta).to_string(),
+            terms,
+            bundle: bundle_dirs.get(&rel).cloned(),
+        };
+        graph.insert_page(rel.clone(), deps);
+        new_cache.pages.insert(rel, entry);
+        summaries.push(summary);
+    }
 
-    if let Err(e) = copy_static_assets(&cfg.src_dir, &cfg.out_dir) {
-        eprintln!("assets: {e}");
+    if let Err(e) = write_redirects(&cfg, &redirects) {
+        eprintln!("redirects: {e}");
+        failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if builtin_404
+        && affected
+            .as_ref()
+            .is_none_or(|a| a.templates.contains("404.html"))
+    {
+        let (html, used) = images::track(|| templates.render_not_found(&site));
+        used_images.insert(out_key(&not_found), used);
+        if let Err(e) = html.and_then(|html| write_html(&cfg, &not_found, &html)) {
+            eprintln!("404: {e}");
+            failed.fetch_add(1, Ordering::Relaxed);
+        }
     }
-    if let Err(e) = write_tag_pages(&templates, &cfg, &cfg.out_dir, &summaries) {
-        eprintln!("tags: {e}");
+
+    if cfg.assets.fingerprint
+        && let Err(e) = manifest.write(&cfg.out_dir)
+    {
+        eprintln!("asset manifest: {e}");
+        failed.fetch_add(1, Ordering::Relaxed);
     }
 
-    let mut new_pages = std::collections::HashMap::new();
-    for md in &md_files {
-        let rel = md
-            .strip_prefix(&cfg.src_dir)
-            .unwrap_or(md)
-            .to_string_lossy()
-            .to_string();
-        if let Ok(h) = cache::file_hash(md) {
-            new_pages.insert(rel, h);
+    // Term pages to refresh: every one on a full build or when the term
+    // template changed, otherwise those listing a page that changed (before
+    // or after the change).
+    let dirty_terms = affected.as_ref().map(|a| {
+        let mut terms = a.terms.clone();
+        for rel in &a.pages {
+            if let Some(deps) = graph.page(rel) {
+                terms.extend(deps.terms.iter().cloned());
+            }
+        }
+        terms
+    });
+    for tax in cfg.taxonomies.iter().filter(|t| t.render) {
+        let only: Option<HashSet<String>> = match (&affected, &dirty_terms) {
+            (Some(a), Some(terms)) => {
+                let templates_changed = a.templates.contains(&tax.term_template())
+                    || (tax.feed && a.templates.contains("feed.xml"));
+                (!templates_changed).then(|| {
+                    terms
+                        .iter()
+                        .filter(|(name, _)| *name == tax.name)
+                        .map(|(_, key)| key.clone())
+                        .collect()
+                })
+            }
+            _ => None,
+        };
+        let index_changed = affected
+            .as_ref()
+            .is_none_or(|a| a.templates.contains(&tax.index_template()));
+        if only.as_ref().is_some_and(HashSet::is_empty) && !index_changed {
+            continue;
         }
+        match write_taxonomy_pages(
+            &templates,
+            &site,
+            &cfg,
+            tax,
+            &cfg.out_dir,
+            &summaries,
+            only.as_ref(),
+        ) {
+            Ok(written) => {
+                for (path, used) in written {
+                    used_images.insert(out_key(&path), used);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", tax.name);
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    used_images.retain(|_, used| !used.is_empty());
+    let keep: BTreeSet<String> = used_images.values().flatten().cloned().collect();
+    if let Err(e) = images::prune(&cfg.out_dir, &keep) {
+        eprintln!("images: {e}");
     }
-    cache_prev.templates_hash = tpl_hash;
-    cache_prev.pages = new_pages;
-    if let Err(e) = cache::save(&cfg.out_dir, &cache_prev) {
+    new_cache.images = used_images;
+
+    if let Err(e) = cache::save(&cfg.out_dir, &new_cache) {
         eprintln!("cache: {e}");
     }
 
     let b = built.load(Ordering::Relaxed);
     let s = skipped.load(Ordering::Relaxed);
-    println!("Build done: {b} built, {s} skipped");
+    let failed = failed.load(Ordering::Relaxed);
+    match failed {
+        0 => println!("Build done: {b} built, {s} skipped"),
+        f => println!("Build done: {b} built, {s} skipped, {f} failed"),
+    }
+    Some(Built { graph, failed })
+}
+
+/// A page whose source has been read and whose output path is known.
+struct PagePlan<'a> {
+    md: &'a Path,
+    rel: String,
+    text: String,
+    hash: String,
+    meta: PageMeta,
+    out_path: PathBuf,
+    /// Parsed document, when parsing was needed to resolve the metadata.
+    doc: Option<Document<PageMeta>>,
+}
+
+impl PagePlan<'_> {
+    fn summary(&self, out_root: &Path, taxonomies: &[TaxonomyConfig]) -> PageSummary {
+        summarize(&self.rel, out_root, &self.out_path, &self.meta, taxonomies)
+    }
 }
 
 fn clean(out: &Path) {
@@ -3,5 +3,8 @@ use clap::Parser;
 fn main() {
     let args = ssg::cli::Args::parse();
 
-    ssg::run(args);
+    if let Err(e) = ssg::run(args) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,739 @@
+//! Conservative HTML, CSS and JavaScript minifiers; the CSS and JavaScript
+//! ones also write source maps.
+//!
+//! All of them only drop comments and whitespace: nothing is renamed or
+//! rewritten, so the output behaves like the input. JavaScript keeps the line
+//! breaks automatic semicolon insertion may depend on. Each CSS or JavaScript
+//! output run is mapped back to the line and column it came from.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Css,
+    Js,
+}
+
+impl Lang {
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "css" | "scss" | "sass" => Some(Self::Css),
+            "js" | "mjs" => Some(Self::Js),
+            _ => None,
+        }
+    }
+
+    /// How a file of this language points at its source map.
+    pub fn map_comment(self, url: &str) -> String {
+        match self {
+            Self::Css => format!("/*# sourceMappingURL={url} */"),
+            Self::Js => format!("//# sourceMappingURL={url}"),
+        }
+    }
+}
+
+/// A syntax error the minifier cannot step over, e.g. an unterminated string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinifyError {
+    pub source: String,
+    /// 1-based line the offending construct starts on.
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for MinifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.source, self.line, self.message)
+    }
+}
+
+impl std::error::Error for MinifyError {}
+
+/// Minifies one or more sources of the same language into a single output.
+pub struct Minifier {
+    lang: Lang,
+    out: String,
+    out_line: usize,
+    out_col: usize,
+    sources: Vec<(String, String)>,
+    segments: Vec<Segment>,
+    /// Source line of the last segment, to add one per line only.
+    mapped: Option<(usize, usize)>,
+}
+
+struct Segment {
+    out_line: usize,
+    out_col: usize,
+    source: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Minifier {
+    pub fn new(lang: Lang) -> Self {
+        Self {
+            lang,
+            out: String::new(),
+            out_line: 0,
+            out_col: 0,
+            sources: Vec::new(),
+            segments: Vec::new(),
+            mapped: None,
+        }
+    }
+
+    /// Appends `text`, minified. `name` is how the source map refers to it.
+    pub fn push(&mut self, name: &str, text: &str) -> Result<(), MinifyError> {
+        if self.lang == Lang::Js && !self.out.is_empty() {
+            // Keeps a file that ends without a semicolon from running into
+            // the next one.
+            self.raw(if self.last() == Some(';') {
+                "\n"
+            } else {
+                ";\n"
+            });
+        }
+        let source = self.sources.len();
+        self.sources.push((name.to_string(), text.to_string()));
+        let mut cur = Cursor::new(text);
+        let res = match self.lang {
+            Lang::Css => self.css(source, &mut cur),
+            Lang::Js => self.js(source, &mut cur),
+        };
+        res.map_err(|(line, message)| MinifyError {
+            source: name.to_string(),
+            line: line + 1,
+            message,
+        })
+    }
+
+    pub fn code(&self) -> &str {
+        &self.out
+    }
+
+    /// Source map (version 3) for the output, which will be served as `file`.
+    /// Sources are embedded, so the map works without them being published.
+    pub fn source_map(&self, file: &str) -> String {
+        let (names, contents): (Vec<_>, Vec<_>) = self.sources.iter().cloned().unzip();
+        serde_json::json!({
+            "version": 3,
+            "file": file,
+            "sources": names,
+            "sourcesContent": contents,
+            "names": [],
+            "mappings": self.mappings(),
+        })
+        .to_string()
+    }
+
+    fn mappings(&self) -> String {
+        let mut out = String::new();
+        let (mut line, mut col) = (0, 0);
+        let (mut source, mut src_line, mut src_col) = (0, 0, 0);
+        for seg in &self.segments {
+            if seg.out_line > line {
+                out.extend(std::iter::repeat_n(';', seg.out_line - line));
+                line = seg.out_line;
+                col = 0;
+            } else if !out.is_empty() && !out.ends_with(';') {
+                out.push(',');
+            }
+            vlq(&mut out, seg.out_col as i64 - col as i64);
+            vlq(&mut out, seg.source as i64 - source as i64);
+            vlq(&mut out, seg.line as i64 - src_line as i64);
+            vlq(&mut out, seg.col as i64 - src_col as i64);
+            (col, source, src_line, src_col) = (seg.out_col, seg.source, seg.line, seg.col);
+        }
+        out
+    }
+
+    fn emit(&mut self, c: char, source: usize, (line, col): (usize, usize)) {
+        if self.out_col == 0 || self.mapped != Some((source, line)) {
+            self.segments.push(Segment {
+                out_line: self.out_line,
+                out_col: self.out_col,
+                source,
+                line,
+                col,
+            });
+            self.mapped = Some((source, line));
+        }
+        self.push_char(c);
+    }
+
+    /// Output that does not come from any source.
+    fn raw(&mut self, s: &str) {
+        s.chars().for_each(|c| self.push_char(c));
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.out.push(c);
+        if c == '\n' {
+            self.out_line += 1;
+            self.out_col = 0;
+        } else {
+            self.out_col += 1;
+        }
+    }
+
+    fn last(&self) -> Option<char> {
+        self.out.chars().next_back()
+    }
+
+    fn css(&mut self, source: usize, cur: &mut Cursor) -> Result<(), (usize, &'static str)> {
+        let mut space = false;
+        while let Some(c) = cur.peek(0) {
+            let at = cur.at();
+            match c {
+                '/' if cur.peek(1) == Some('*') => {
+                    cur.skip_block_comment()?;
+                    space = true;
+                }
+                c if c.is_whitespace() => {
+                    cur.bump();
+                    space = true;
+                }
+                _ => {
+                    let keep = |p: char| !"{};,:>(".contains(p) && !"{};,>)".contains(c);
+                    if space && self.last().is_some_and(keep) {
+                        self.emit(' ', source, at);
+                    }
+                    space = false;
+                    if c == '}' && self.last() == Some(';') {
+                        self.out.pop();
+                        self.out_col -= 1;
+                    }
+                    if c == '"' || c == '\'' {
+                        self.copy_string(source, cur, false)?;
+                    } else {
+                        cur.bump();
+                        self.emit(c, source, at);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn js(&mut self, source: usize, cur: &mut Cursor) -> Result<(), (usize, &'static str)> {
+        let (mut space, mut newline) = (false, false);
+        // Per open parenthesis, whether it follows `if`, `for`, `while` or
+        // `with`; a `/` after the matching `)` then starts a regex.
+        let mut parens = Vec::new();
+        let mut after_condition = false;
+        while let Some(c) = cur.peek(0) {
+            let at = cur.at();
+            match c {
+                '/' if cur.peek(1) == Some('/') => {
+                    while cur.peek(0).is_some_and(|c| c != '\n') {
+                        cur.bump();
+                    }
+                }
+                '/' if cur.peek(1) == Some('*') => {
+                    let start = cur.at().0;
+                    cur.skip_block_comment()?;
+                    if cur.at().0 > start {
+                        newline = true;
+                    } else {
+                        space = true;
+                    }
+                }
+                '\n' => {
+                    cur.bump();
+                    newline = true;
+                }
+                c if c.is_whitespace() => {
+                    cur.bump();
+                    space = true;
+                }
+                _ => {
+                    let prev = self.last();
+                    if newline && prev.is_some() {
+                        let joins =
+                            prev.is_some_and(|p| "{;,([\n".contains(p)) || ")]}".contains(c);
+                        if !joins {
+                            self.push_char('\n');
+                        }
+                    } else if space && prev.is_some_and(|p| js_needs_space(p, c)) {
+                        self.emit(' ', source, at);
+                    }
+                    (space, newline) = (false, false);
+                    match c {
+                        '"' | '\'' => self.copy_string(source, cur, false)?,
+                        '`' => self.copy_string(source, cur, true)?,
+                        '/' if self.regex_allowed(after_condition) => {
+                            self.copy_regex(source, cur)?
+                        }
+                        _ => {
+                            if c == '(' {
+                                let word = self.last_word();
+                                parens
+                                    .push(matches!(word.as_str(), "if" | "for" | "while" | "with"));
+                            } else if c == ')' {
+                                after_condition = parens.pop().unwrap_or(false);
+                            }
+                            cur.bump();
+                            self.emit(c, source, at);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies a quoted string, quotes included. Only template literals may
+    /// span lines.
+    fn copy_string(
+        &mut self,
+        source: usize,
+        cur: &mut Cursor,
+        multiline: bool,
+    ) -> Result<(), (usize, &'static str)> {
+        let start = cur.at();
+        let quote = cur.bump().unwrap_or('"');
+        self.emit(quote, source, start);
+        loop {
+            let at = cur.at();
+            match cur.bump() {
+                None => return Err((start.0, "unterminated string")),
+                Some('\n') if !multiline => return Err((start.0, "unterminated string")),
+                Some('\\') => {
+                    self.emit('\\', source, at);
+                    let at = cur.at();
+                    match cur.bump() {
+                        Some(c) => self.emit(c, source, at),
+                        None => return Err((start.0, "unterminated string")),
+                    }
+                }
+                Some(c) => {
+                    self.emit(c, source, at);
+                    if c == quote {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn copy_regex(&mut self, source: usize, cur: &mut Cursor) -> Result<(), (usize, &'static str)> {
+        let start = cur.at();
+        cur.bump();
+        self.emit('/', source, start);
+        let mut class = false;
+        loop {
+            let at = cur.at();
+            match cur.bump() {
+                None | Some('\n') => return Err((start.0, "unterminated regular expression")),
+                Some('\\') => {
+                    self.emit('\\', source, at);
+                    let at = cur.at();
+                    match cur.bump() {
+                        Some(c) if c != '\n' => self.emit(c, source, at),
+                        _ => return Err((start.0, "unterminated regular expression")),
+                    }
+                }
+                Some(c) => {
+                    self.emit(c, source, at);
+                    match c {
+                        '[' => class = true,
+                        ']' => class = false,
+                        '/' if !class => return Ok(()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether a `/` here starts a regular expression rather than a division,
+    /// judged by what precedes it. `after_condition` tells whether a `)` just
+    /// before closes the condition of an `if` or a loop.
+    fn regex_allowed(&self, after_condition: bool) -> bool {
+        let Some(prev) = self.last() else {
+            return true;
+        };
+        if "(,=:[!&|?{};+-*%<>~^\n".contains(prev) {
+            return true;
+        }
+        if prev == ')' {
+            return after_condition;
+        }
+        matches!(
+            self.last_word().as_str(),
+            "return"
+                | "typeof"
+                | "instanceof"
+                | "in"
+                | "of"
+                | "new"
+                | "delete"
+                | "void"
+                | "throw"
+                | "case"
+                | "do"
+                | "else"
+                | "yield"
+                | "await"
+        )
+    }
+
+    /// The identifier or keyword the output ends with, if any.
+    fn last_word(&self) -> String {
+        let len = self.out.len()
+            - self
+                .out
+                .chars()
+                .rev()
+                .take_while(|&c| is_ident(c))
+                .map(char::len_utf8)
+                .sum::<usize>();
+        self.out[len..].to_string()
+    }
+}
+
+/// Elements around which whitespace never renders.
+const BLOCK_TAGS: &[&str] = &[
+    "!doctype",
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "option",
+    "p",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements whose content is copied untouched.
+const RAW_TAGS: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Removes comments and collapses whitespace in an HTML document. Runs of
+/// whitespace become one space, or nothing next to block-level tags; the
+/// content of `<pre>`, `<textarea>`, `<script>` and `<style>` and quoted
+/// attribute values are left as they are. Conditional comments are kept.
+pub fn minify_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut space = false;
+    // Whether the last thing written was a block-level tag (or nothing).
+    let mut after_block = true;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("<!--") && !rest.starts_with("<!--[if") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let starts_tag = c == '<'
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|n| n.is_ascii_alphabetic() || n == '/' || n == '!');
+        if starts_tag {
+            let Some(end) = tag_end(rest) else {
+                out.push_str(rest);
+                break;
+            };
+            let tag = &rest[..end];
+            let closing = tag.starts_with("</");
+            let name = tag
+                .trim_start_matches(['<', '/'])
+                .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let block = BLOCK_TAGS.contains(&name.as_str());
+            if space && !after_block && !block {
+                out.push(' ');
+            }
+            space = false;
+            after_block = block;
+            collapse_tag(tag, &mut out);
+            rest = &rest[end..];
+
+            if !closing && RAW_TAGS.contains(&name.as_str()) {
+                let close = format!("</{name}");
+                let len = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            space = true;
+        } else {
+            if space && !after_block {
+                out.push(' ');
+            }
+            space = false;
+            after_block = false;
+            out.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Byte offset just past the `>` closing the tag `rest` starts with,
+/// skipping over quoted attribute values.
+fn tag_end(rest: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in rest.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn collapse_tag(tag: &str, out: &mut String) {
+    let mut quote = None;
+    let mut space = false;
+    for c in tag.chars() {
+        match quote {
+            Some(q) => {
+                out.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => space = true,
+            None => {
+                if space && c != '>' {
+                    out.push(' ');
+                }
+                space = false;
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                out.push(c);
+            }
+        }
+    }
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || !c.is_ascii()
+}
+
+/// Whether dropping the whitespace between `prev` and `next` would change how
+/// the code tokenizes.
+fn js_needs_space(prev: char, next: char) -> bool {
+    (is_ident(prev) && is_ident(next))
+        || (prev == next && (prev == '+' || prev == '-'))
+        // `a / /re/` must not turn into a comment.
+        || (prev == '/' && (next == '/' || next == '*'))
+        || (prev.is_ascii_digit() && next == '.')
+}
+
+/// Base64 VLQ, as used by source map `mappings`.
+fn vlq(out: &mut String, value: i64) {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut v = if value < 0 {
+        ((-value as u64) << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+    loop {
+        let mut digit = (v & 31) as usize;
+        v >>= 5;
+        if v > 0 {
+            digit |= 32;
+        }
+        out.push(DIGITS[digit] as char);
+        if v == 0 {
+            break;
+        }
+    }
+}
+
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Cursor {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 0,
+            col: 0,
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    /// Zero-based line and column of the next character.
+    fn at(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 0;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), (usize, &'static str)> {
+        let start = self.line;
+        self.bump();
+        self.bump();
+        while self.peek(0).is_some() {
+            if self.peek(0) == Some('*') && self.peek(1) == Some('/') {
+                self.bump();
+                self.bump();
+                return Ok(());
+            }
+            self.bump();
+        }
+        Err((start, "unterminated comment"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(lang: Lang, text: &str) -> String {
+        let mut m = Minifier::new(lang);
+        m.push("in", text).unwrap();
+        m.code().to_string()
+    }
+
+    #[test]
+    fn css_drops_comments_and_whitespace_only() {
+        let css = "/* head */\na:hover ,\nb > i {\n  color: red ;\n  content: \"a  ;b\";\n}\n\
+                   @media (min-width: 10px) and (max-width: 20px) { p { margin: 0 auto; } }\n";
+        assert_eq!(
+            minify(Lang::Css, css),
+            "a:hover,b>i{color:red;content:\"a  ;b\"}\
+             @media (min-width:10px) and (max-width:20px){p{margin:0 auto}}"
+        );
+        assert_eq!(minify(Lang::Css, "a :hover{}"), "a :hover{}");
+    }
+
+    #[test]
+    fn js_keeps_strings_regexes_and_line_breaks() {
+        let js = "// setup\nconst a = 1 + +b; /* sum */\nlet re = /\\/[/]+/g, s = 'x // y';\n\n\
+                  if (a) {\n  return a / 2\n}\nx\n++y\nconst t = `multi\nline`;\n";
+        assert_eq!(
+            minify(Lang::Js, js),
+            "const a=1+ +b;let re=/\\/[/]+/g,s='x // y';if(a){return a/2}\nx\n++y\nconst t=`multi\nline`;"
+        );
+    }
+
+    #[test]
+    fn js_tells_regexes_from_divisions_around_parentheses() {
+        assert_eq!(
+            minify(Lang::Js, "if (x) /a b/.test(s)\nwhile (f(y)) /c/g.exec(t)"),
+            "if(x)/a b/.test(s)\nwhile(f(y))/c/g.exec(t)"
+        );
+        assert_eq!(minify(Lang::Js, "n = (a + b) / 2 / c"), "n=(a+b)/2/c");
+        assert_eq!(
+            minify(Lang::Js, "q = a / /re/.source.length"),
+            "q=a/ /re/.source.length"
+        );
+        assert_eq!(minify(Lang::Js, "r = /x/ / 2"), "r=/x/ /2");
+    }
+
+    #[test]
+    fn html_collapses_whitespace_but_not_preformatted_text() {
+        let html = "<!doctype html>\n<html>\n  <head>\n    <title> Hi </title>\n  </head>\n\
+                    <body>\n    <!-- nav -->\n    <p>Hello,   <a   href=\"/x\"\n  title=\"a  b\" >world</a> !</p>\n\
+                    <pre>  keep\n    this </pre>\n<textarea>\n  raw </textarea>\n\
+                    <!--[if IE]><p>old</p><![endif]-->\n</body>\n</html>\n";
+        assert_eq!(
+            minify_html(html),
+            "<!doctype html><html><head><title>Hi</title></head><body>\
+             <p>Hello, <a href=\"/x\" title=\"a  b\">world</a> !</p>\
+             <pre>  keep\n    this </pre> <textarea>\n  raw </textarea> \
+             <!--[if IE]><p>old</p><![endif]--></body></html>"
+        );
+        assert_eq!(minify_html("a < b"), "a < b");
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let mut m = Minifier::new(Lang::Js);
+        let err = m
+            .push("app.js", "let a = 1;\nlet b = 'open;\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "app.js:2: unterminated string");
+
+        let mut m = Minifier::new(Lang::Css);
+        let err = m.push("a.css", "a{}\n\n/* never closed").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+
+    #[test]
+    fn source_map_points_back_at_each_line() {
+        let mut m = Minifier::new(Lang::Css);
+        m.push("a.css", "a {\n  color: red;\n}\n").unwrap();
+        m.push("b.css", "b{}").unwrap();
+        assert_eq!(m.code(), "a{color:red}b{}");
+
+        let map: serde_json::Value = serde_json::from_str(&m.source_map("all.css")).unwrap();
+        assert_eq!(map["sources"], serde_json::json!(["a.css", "b.css"]));
+        // a.css 1:1, a.css 2:3, a.css 3:1, then b.css 1:1.
+        assert_eq!(map["mappings"], "AAAA,EACE,SACF,CCFA");
+
+        let mut s = String::new();
+        vlq(&mut s, 16);
+        vlq(&mut s, -1);
+        assert_eq!(s, "gBD");
+    }
+}
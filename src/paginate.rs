@@ -25,6 +25,98 @@ pub fn neighbors(info: PageInfo) -> (Option<usize>, Option<usize>) {
     (prev, next)
 }
 
+/// Template view of one page of a paginated listing. Page numbers are
+/// 1-based, item offsets 0-based.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PaginationView {
+    pub current: usize,
+    pub total: usize,
+    pub first_url: String,
+    pub last_url: String,
+    pub prev_url: Option<String>,
+    pub next_url: Option<String>,
+    /// Numbered links around the current page; `None` numbers mark gaps.
+    pub pages: Vec<PageLink>,
+    pub per_page: usize,
+    pub total_items: usize,
+    /// Offset of the first item on this page within the whole listing.
+    pub start: usize,
+    /// Offset one past the last item on this page.
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PageLink {
+    pub number: Option<usize>,
+    pub url: Option<String>,
+    pub current: bool,
+}
+
+impl PaginationView {
+    /// Builds the view for page `info` of `total_items` split by `per_page`,
+    /// with `url(index)` giving the URL of a 0-based page index.
+    pub fn new(
+        info: PageInfo,
+        per_page: usize,
+        total_items: usize,
+        window: usize,
+        url: impl Fn(usize) -> String,
+    ) -> Self {
+        let (prev, next) = neighbors(info);
+        let start = (info.index * per_page).min(total_items);
+        let pages = page_window(info, window)
+            .into_iter()
+            .map(|idx| PageLink {
+                number: idx.map(|i| i + 1),
+                url: idx.map(&url),
+                current: idx == Some(info.index),
+            })
+            .collect();
+        Self {
+            current: info.index + 1,
+            total: info.total_pages,
+            first_url: url(0),
+            last_url: url(info.total_pages.saturating_sub(1)),
+            prev_url: prev.map(&url),
+            next_url: next.map(&url),
+            pages,
+            per_page,
+            total_items,
+            start,
+            end: (start + per_page).min(total_items),
+        }
+    }
+}
+
+/// Page indices to link from `info`: the first and last page plus `window`
+/// pages either side of the current one, with `None` where pages are elided.
+/// A gap of a single page shows that page instead of an ellipsis.
+pub fn page_window(info: PageInfo, window: usize) -> Vec<Option<usize>> {
+    if info.total_pages == 0 {
+        return Vec::new();
+    }
+    let last = info.total_pages - 1;
+    let lo = info.index.saturating_sub(window);
+    let hi = (info.index + window).min(last);
+
+    let mut out = Vec::new();
+    let mut prev: Option<usize> = None;
+    let wanted = std::iter::once(0)
+        .chain(lo..=hi)
+        .chain(std::iter::once(last));
+    for i in wanted {
+        match prev {
+            Some(p) if i <= p => continue,
+            Some(p) if i == p + 2 => out.push(Some(p + 1)),
+            Some(p) if i > p + 2 => out.push(None),
+            _ => {}
+        }
+        out.push(Some(i));
+        prev = Some(i);
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,4 +137,38 @@ mod tests {
         assert_eq!(pages[1].len(), 10);
         assert_eq!(pages[2].len(), 3);
     }
+
+    #[test]
+    fn window_elides_distant_pages() {
+        let at = |index, total_pages| PageInfo { index, total_pages };
+        assert_eq!(page_window(at(0, 1), 2), [Some(0)]);
+        assert_eq!(page_window(at(0, 10), 1), [Some(0), Some(1), None, Some(9)]);
+        assert_eq!(
+            page_window(at(5, 10), 1),
+            [Some(0), None, Some(4), Some(5), Some(6), None, Some(9)]
+        );
+        // A single hidden page is shown rather than replaced by a gap.
+        assert_eq!(
+            page_window(at(3, 6), 1),
+            [Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)]
+        );
+        assert!(page_window(at(0, 0), 2).is_empty());
+    }
+
+    #[test]
+    fn view_links_and_offsets() {
+        let info = PageInfo {
+            index: 2,
+            total_pages: 3,
+        };
+        let v = PaginationView::new(info, 10, 23, 2, |i| format!("/p{i}/"));
+        assert_eq!(v.current, 3);
+        assert_eq!(v.first_url, "/p0/");
+        assert_eq!(v.last_url, "/p2/");
+        assert_eq!(v.prev_url.as_deref(), Some("/p1/"));
+        assert_eq!(v.next_url, None);
+        assert_eq!((v.start, v.end), (20, 23));
+        assert!(v.pages[2].current);
+        assert_eq!(v.pages.len(), 3);
+    }
 }
@@ -0,0 +1,464 @@
+//! The asset stage, run on the asset list before fingerprinting and copying.
+//!
+//! - `.scss`/`.sass` entry points compile to `.css`; partials (`_*.scss`)
+//!   are only reachable through `@use` and `@import`.
+//! - With `minify` on, CSS and JavaScript are minified, each with a `.map`
+//!   next to it unless `source_maps` is off. Maps of compiled SCSS point
+//!   at the intermediate CSS, listed as `<file>.scss.css`.
+//! - `[[assets.bundles]]` concatenate their inputs into one output.
+//!
+//! Results are staged under `<out>/.ssg-cache/assets/`, named by a hash of
+//! everything that goes into them and recorded in the build cache, so an
+//! unchanged output is not rebuilt. The directory is only created when
+//! there is something to build, and nothing else may write into it.
+
+use rayon::prelude::*;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{self, BuildCache, BuiltAsset},
+    config::{BundleConfig, SiteConfig},
+    minify::{Lang, Minifier},
+};
+
+/// Where built assets wait to be copied, relative to the output root.
+pub const STAGE_DIR: &str = ".ssg-cache/assets";
+
+/// What the asset stage produced.
+#[derive(Debug, Default)]
+pub struct Processed {
+    /// Every built output, keyed by its path relative to the output root.
+    pub built: HashMap<String, BuiltAsset>,
+    /// For each built destination, the source to blame in route conflicts.
+    pub sources: HashMap<PathBuf, String>,
+    /// Why outputs could not be built. Those outputs are left out; the
+    /// others are built regardless.
+    pub errors: Vec<io::Error>,
+}
+
+struct Job {
+    dest: PathBuf,
+    lang: Lang,
+    inputs: Vec<PathBuf>,
+    label: String,
+}
+
+/// Replaces the entries of `assets` that need building with their staged
+/// results, adding source maps and bundles.
+pub fn process(
+    cfg: &SiteConfig,
+    prev: &BuildCache,
+    assets: &mut Vec<(PathBuf, PathBuf)>,
+) -> io::Result<Processed> {
+    let opts = &cfg.assets;
+    let mut processed = Processed::default();
+    let mut jobs = Vec::new();
+    let mut scss_deps = Vec::new();
+    assets.retain(|(src, dest)| {
+        let ext = extension(src);
+        if ext == "scss" || ext == "sass" {
+            scss_deps.push(src.clone());
+            let partial = src
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('_'));
+            if !partial {
+                jobs.push(Job {
+                    dest: dest.with_extension("css"),
+                    lang: Lang::Css,
+                    inputs: vec![src.clone()],
+                    label: label(&cfg.src_dir, src),
+                });
+            }
+            return false;
+        }
+        match Lang::from_ext(&ext) {
+            Some(lang) if opts.minify => {
+                jobs.push(Job {
+                    dest: dest.clone(),
+                    lang,
+                    inputs: vec![src.clone()],
+                    label: label(&cfg.src_dir, src),
+                });
+                false
+            }
+            _ => true,
+        }
+    });
+    for bundle in &opts.bundles {
+        match bundle_job(cfg, bundle) {
+            Ok(job) => jobs.push(job),
+            Err(e) => processed.errors.push(e),
+        }
+    }
+
+    // Partials may be imported from anywhere, so every SCSS source counts
+    // towards every compiled output.
+    scss_deps.sort();
+    let mut scss_hash = String::new();
+    for dep in &scss_deps {
+        scss_hash.push_str(&cache::file_hash(dep)?);
+    }
+    let stage_dir = cfg.out_dir.join(STAGE_DIR);
+    if jobs.is_empty() {
+        match fs::remove_dir_all(&stage_dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => return Ok(processed),
+        }
+    }
+    fs::create_dir_all(&stage_dir)?;
+
+    let results: Vec<(Job, io::Result<BuiltAsset>)> = jobs
+        .into_par_iter()
+        .map(|job| {
+            let built = build_job(cfg, prev, &stage_dir, &scss_hash, &job);
+            (job, built)
+        })
+        .collect();
+
+    for (job, built) in results {
+        let built = match built {
+            Ok(b) => b,
+            Err(e) => {
+                processed.errors.push(e);
+                continue;
+            }
+        };
+        let ext = extension(&job.dest);
+        assets.push((
+            stage_dir.join(format!("{}.{ext}", built.key)),
+            job.dest.clone(),
+        ));
+        processed
+            .sources
+            .insert(job.dest.clone(), job.label.clone());
+        if built.map {
+            let map_dest = map_path(&job.dest);
+            assets.push((
+                stage_dir.join(format!("{}.{ext}.map", built.key)),
+                map_dest.clone(),
+            ));
+            processed.sources.insert(map_dest, job.label);
+        }
+        processed.built.insert(rel(&cfg.out_dir, &job.dest), built);
+    }
+    assets.sort_by(|a, b| a.1.cmp(&b.1));
+
+    prune(&stage_dir, &processed)?;
+    Ok(processed)
+}
+
+fn bundle_job(cfg: &SiteConfig, bundle: &BundleConfig) -> io::Result<Job> {
+    let dest = cfg.out_dir.join(bundle.output.trim_start_matches('/'));
+    let lang = Lang::from_ext(&extension(&dest))
+        .filter(|_| !bundle.output.ends_with(".scss") && !bundle.output.ends_with(".sass"))
+        .ok_or_else(|| {
+            invalid(format!(
+                "bundle `{}`: not a .css or .js output",
+                bundle.output
+            ))
+        })?;
+    let inputs = bundle
+        .inputs
+        .iter()
+        .map(|input| {
+            let path = cfg.src_dir.join(input.trim_start_matches('/'));
+            match path.is_file() {
+                true => Ok(path),
+                false => Err(invalid(format!(
+                    "bundle `{}`: no input `{input}`",
+                    bundle.output
+                ))),
+            }
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(Job {
+        dest,
+        lang,
+        inputs,
+        label: format!("bundle `{}`", bundle.output),
+    })
+}
+
+fn build_job(
+    cfg: &SiteConfig,
+    prev: &BuildCache,
+    stage_dir: &Path,
+    scss_hash: &str,
+    job: &Job,
+) -> io::Result<BuiltAsset> {
+    let opts = &cfg.assets;
+    let mut inputs = Vec::new();
+    let mut key = format!(
+        "{}|{}|{}|{}",
+        cache::TOOL_VERSION,
+        opts.minify,
+        opts.source_maps,
+        rel(&cfg.out_dir, &job.dest)
+    );
+    for input in &job.inputs {
+        let name = label(&cfg.src_dir, input);
+        key.push_str(&format!("|{name}:{}", cache::file_hash(input)?));
+        if is_scss(input) {
+            key.push_str(scss_hash);
+        }
+        inputs.push(name);
+    }
+    let key = cache::hash_bytes(key.as_bytes());
+    let map = opts.minify && opts.source_maps;
+    let ext = extension(&job.dest);
+    let staged = stage_dir.join(format!("{key}.{ext}"));
+
+    let out_rel = rel(&cfg.out_dir, &job.dest);
+    if prev.assets.get(&out_rel).is_some_and(|b| b.key == key) && staged.is_file() {
+        return Ok(BuiltAsset { key, inputs, map });
+    }
+
+    let mut minifier = Minifier::new(job.lang);
+    let mut code = String::new();
+    for (input, name) in job.inputs.iter().zip(&inputs) {
+        let text = if is_scss(input) {
+            compile_scss(cfg, input)?
+        } else {
+            fs::read_to_string(input).map_err(|e| invalid(format!("{name}: {e}")))?
+        };
+        if opts.minify {
+            // grass has no source maps, so a compiled input is mapped as
+            // the CSS it compiled to, under a name that says so.
+            let source = match is_scss(input) {
+                true => format!("/{name}.css"),
+                false => format!("/{name}"),
+            };
+            minifier
+                .push(&source, &text)
+                .map_err(|e| invalid(format!("{name}:{}: {}", e.line, e.message)))?;
+        } else {
+            if !code.is_empty() && job.lang == Lang::Js {
+                code.push_str(";\n");
+            }
+            code.push_str(&text);
+        }
+    }
+    if opts.minify {
+        code = minifier.code().to_string();
+    }
+    if map {
+        let file = file_name(&job.dest);
+        let map_name = file_name(&map_path(&job.dest));
+        code.push('\n');
+        code.push_str(&job.lang.map_comment(&map_name));
+        fs::write(
+            stage_dir.join(format!("{key}.{ext}.map")),
+            minifier.source_map(&file),
+        )?;
+    }
+    fs::write(&staged, code)?;
+    Ok(BuiltAsset { key, inputs, map })
+}
+
+/// Compiles one SCSS or Sass entry point; errors carry the file, line and
+/// column.
+fn compile_scss(cfg: &SiteConfig, path: &Path) -> io::Result<String> {
+    let mut options = grass::Options::default().load_path(&cfg.src_dir);
+    if let Some(dir) = path.parent() {
+        options = options.load_path(dir);
+    }
+    grass::from_path(path, &options).map_err(|e| invalid(e.to_string().trim_end().to_string()))
+}
+
+/// Removes staged files no output refers to any more.
+fn prune(stage_dir: &Path, processed: &Processed) -> io::Result<()> {
+    let live: BTreeSet<&str> = processed.built.values().map(|b| b.key.as_str()).collect();
+    for entry in fs::read_dir(stage_dir)? {
+        let path = entry?.path();
+        let key = file_name(&path);
+        let key = key.split('.').next().unwrap_or_default();
+        if !live.contains(key) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn map_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".map");
+    PathBuf::from(name)
+}
+
+fn is_scss(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "scss" | "sass")
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn label(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+fn rel(out_root: &Path, path: &Path) -> String {
+    label(out_root, path)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AssetsConfig;
+    use tempfile::tempdir;
+
+    fn site(root: &Path, assets: AssetsConfig) -> SiteConfig {
+        SiteConfig {
+            src_dir: root.join("src"),
+            out_dir: root.join("out"),
+            assets,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compiles_scss_and_skips_partials() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path(), AssetsConfig::default());
+        let css = cfg.src_dir.join("css");
+        fs::create_dir_all(&css).unwrap();
+        fs::write(css.join("_vars.scss"), "$fg: red;").unwrap();
+        fs::write(
+            css.join("main.scss"),
+            "@use 'vars';\na { b { color: vars.$fg; } }",
+        )
+        .unwrap();
+        fs::write(cfg.src_dir.join("app.js"), "let a = 1;").unwrap();
+
+        let mut assets = vec![
+            (css.join("_vars.scss"), cfg.out_dir.join("css/_vars.scss")),
+            (css.join("main.scss"), cfg.out_dir.join("css/main.scss")),
+            (cfg.src_dir.join("app.js"), cfg.out_dir.join("app.js")),
+        ];
+        let processed = process(&cfg, &BuildCache::default(), &mut assets).unwrap();
+
+        let dests: Vec<_> = assets.iter().map(|(_, d)| d.clone()).collect();
+        assert_eq!(
+            dests,
+            [cfg.out_dir.join("app.js"), cfg.out_dir.join("css/main.css")]
+        );
+        let out = fs::read_to_string(&assets[1].0).unwrap();
+        assert!(out.contains("a b {\n  color: red;\n}"));
+        assert_eq!(processed.sources[&assets[1].1], "css/main.scss");
+    }
+
+    #[test]
+    fn minified_bundles_get_source_maps_and_are_reused() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(
+            tmp.path(),
+            AssetsConfig {
+                minify: true,
+                bundles: vec![BundleConfig {
+                    output: "js/site.js".into(),
+                    inputs: vec!["a.js".into(), "b.js".into()],
+                }],
+                ..Default::default()
+            },
+        );
+        fs::create_dir_all(&cfg.src_dir).unwrap();
+        fs::write(cfg.src_dir.join("a.js"), "let a = 1\n").unwrap();
+        fs::write(cfg.src_dir.join("b.js"), "// b\nlet b = 2;\n").unwrap();
+
+        let mut assets = Vec::new();
+        let processed = process(&cfg, &BuildCache::default(), &mut assets).unwrap();
+        let bundle = &assets[0];
+        assert_eq!(bundle.1, cfg.out_dir.join("js/site.js"));
+        assert_eq!(
+            fs::read_to_string(&bundle.0).unwrap(),
+            "let a=1;\nlet b=2;\n//# sourceMappingURL=site.js.map"
+        );
+        assert_eq!(assets[1].1, cfg.out_dir.join("js/site.js.map"));
+        let map = fs::read_to_string(&assets[1].0).unwrap();
+        assert!(map.contains(r#""sources":["/a.js","/b.js"]"#));
+
+        // An unchanged bundle is not rebuilt.
+        let staged = bundle.0.clone();
+        let stamp = fs::metadata(&staged).unwrap().modified().unwrap();
+        let prev = BuildCache {
+            assets: processed.built,
+            ..Default::default()
+        };
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let mut again = Vec::new();
+        process(&cfg, &prev, &mut again).unwrap();
+        assert_eq!(again[0].0, staged);
+        assert_eq!(fs::metadata(&staged).unwrap().modified().unwrap(), stamp);
+    }
+
+    #[test]
+    fn stages_nothing_without_jobs() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(tmp.path(), AssetsConfig::default());
+        fs::create_dir_all(&cfg.src_dir).unwrap();
+        fs::write(cfg.src_dir.join("a.js"), "let a = 1;").unwrap();
+        let plain = vec![(cfg.src_dir.join("a.js"), cfg.out_dir.join("a.js"))];
+
+        let mut assets = plain.clone();
+        process(&cfg, &BuildCache::default(), &mut assets).unwrap();
+        assert_eq!(assets, plain);
+        assert!(!cfg.out_dir.exists());
+
+        // What an earlier build staged goes once there are no jobs left.
+        fs::create_dir_all(cfg.out_dir.join(STAGE_DIR)).unwrap();
+        process(&cfg, &BuildCache::default(), &mut assets).unwrap();
+        assert!(!cfg.out_dir.join(STAGE_DIR).exists());
+    }
+
+    #[test]
+    fn errors_report_file_and_line() {
+        let tmp = tempdir().unwrap();
+        let cfg = site(
+            tmp.path(),
+            AssetsConfig {
+                minify: true,
+                ..Default::default()
+            },
+        );
+        fs::create_dir_all(&cfg.src_dir).unwrap();
+        fs::write(cfg.src_dir.join("bad.scss"), "a {\n  color: $missing;\n}").unwrap();
+        fs::write(cfg.src_dir.join("bad.js"), "\nlet s = 'open\n").unwrap();
+        fs::write(cfg.src_dir.join("good.js"), "let a = 1;").unwrap();
+        let mut assets = vec![
+            (cfg.src_dir.join("bad.scss"), cfg.out_dir.join("bad.scss")),
+            (cfg.src_dir.join("bad.js"), cfg.out_dir.join("bad.js")),
+            (cfg.src_dir.join("good.js"), cfg.out_dir.join("good.js")),
+        ];
+        let processed = process(&cfg, &BuildCache::default(), &mut assets).unwrap();
+
+        // The failures are reported and left out; the rest still builds.
+        let errors: Vec<String> = processed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("bad.scss:2:"), "{errors:?}");
+        assert_eq!(errors[1], "bad.js:2: unterminated string");
+        let dests: Vec<_> = assets.iter().map(|(_, d)| d.clone()).collect();
+        assert_eq!(
+            dests,
+            [cfg.out_dir.join("good.js"), cfg.out_dir.join("good.js.map")]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::SiteConfig,
+    routing::{absolute_url, out_path_for_url, url_for_out_path, write_html},
+};
+
+const NETLIFY_FILE: &str = "_redirects";
+const NGINX_FILE: &str = "redirects.nginx.conf";
+
+/// An old URL that should send visitors to a page's current URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    pub out_path: PathBuf,
+}
+
+impl Redirect {
+    /// Redirect from `alias` to the page written at `target`.
+    pub fn new(out_root: &Path, alias: &str, target: &Path) -> Self {
+        // Aliases are literal old URLs, so they never get the ugly `.html` form.
+        let out_path = out_path_for_url(out_root, alias, false);
+        Self {
+            from: url_for_out_path(out_root, &out_path),
+            to: url_for_out_path(out_root, target),
+            out_path,
+        }
+    }
+}
+
+/// Writes a redirect page for every alias, plus `_redirects` and an nginx
+/// map when enabled in `site.toml`.
+pub fn write_redirects(cfg: &SiteConfig, redirects: &[Redirect]) -> io::Result<()> {
+    for r in redirects {
+        write_redirect_page(cfg, r)?;
+    }
+
+    if cfg.redirects.netlify {
+        fs::write(cfg.out_dir.join(NETLIFY_FILE), netlify_rules(redirects))?;
+    }
+    if cfg.redirects.nginx {
+        fs::write(cfg.out_dir.join(NGINX_FILE), nginx_map(redirects))?;
+    }
+    Ok(())
+}
+
+/// The server rule files `write_redirects` writes, with a label for each,
+/// so they can be claimed like any other output.
+pub fn rule_files(cfg: &SiteConfig) -> Vec<(PathBuf, &'static str)> {
+    let mut files = Vec::new();
+    if cfg.redirects.netlify {
+        files.push((cfg.out_dir.join(NETLIFY_FILE), "netlify redirects"));
+    }
+    if cfg.redirects.nginx {
+        files.push((cfg.out_dir.join(NGINX_FILE), "nginx redirects"));
+    }
+    files
+}
+
+/// Writes the HTML page that sends visitors from `r.from` to `r.to`.
+pub fn write_redirect_page(cfg: &SiteConfig, r: &Redirect) -> io::Result<()> {
+    if let Some(parent) = r.out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let html = redirect_html(&absolute_url(&cfg.base_url, &r.to));
+    write_html(cfg, &r.out_path, &html)
+}
+
+fn redirect_html(to: &str) -> String {
+    let to = escape_attr(to);
+    format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n  <meta charset=\"utf-8\" />\n  \
+         <title>Redirecting…</title>\n  <link rel=\"canonical\" href=\"{to}\" />\n  \
+         <meta name=\"robots\" content=\"noindex\" />\n  \
+         <meta http-equiv=\"refresh\" content=\"0; url={to}\" />\n</head>\n<body>\n  \
+         <p>This page has moved to <a href=\"{to}\">{to}</a>.</p>\n</body>\n</html>\n"
+    )
+}
+
+fn netlify_rules(redirects: &[Redirect]) -> String {
+    redirects
+        .iter()
+        .map(|r| format!("{} {} 301\n", r.from, r.to))
+        .collect()
+}
+
+/// An nginx `map` from old to new URIs, meant to be paired with
+/// `if ($redirect_uri) { return 301 $redirect_uri; }`.
+fn nginx_map(redirects: &[Redirect]) -> String {
+    let mut out = String::from("map $uri $redirect_uri {\n");
+    for r in redirects {
+        out.push_str(&format!(
+            "    {} {};\n",
+            nginx_string(&r.from),
+            nginx_string(&r.to)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// A double-quoted nginx string, so spaces and `;` stay part of the value.
+fn nginx_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn escape_attr(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alias_maps_to_index_file_and_target_url() {
+        let out = Path::new("/o");
+        let r = Redirect::new(out, "old-post", Path::new("/o/posts/new/index.html"));
+        assert_eq!(r.from, "/old-post/");
+        assert_eq!(r.to, "/posts/new/");
+        assert_eq!(r.out_path, Path::new("/o/old-post/index.html"));
+
+        let r = Redirect::new(out, "/legacy/page.html", Path::new("/o/posts/new.html"));
+        assert_eq!(r.from, "/legacy/page.html");
+        assert_eq!(r.out_path, Path::new("/o/legacy/page.html"));
+    }
+
+    #[test]
+    fn redirect_page_refreshes_and_is_canonical() {
+        let html = redirect_html("https://example.com/posts/new/");
+        assert!(html.contains(r#"<link rel="canonical" href="https://example.com/posts/new/" />"#));
+        assert!(html.contains(r#"content="0; url=https://example.com/posts/new/""#));
+    }
+
+    #[test]
+    fn server_rule_formats() {
+        let rs = vec![Redirect::new(
+            Path::new("/o"),
+            "/old/",
+            Path::new("/o/new/index.html"),
+        )];
+        assert_eq!(netlify_rules(&rs), "/old/ /new/ 301\n");
+        assert_eq!(
+            nginx_map(&rs),
+            "map $uri $redirect_uri {\n    \"/old/\" \"/new/\";\n}\n"
+        );
+        assert_eq!(nginx_string(r#"/a b;"c"\"#), r#""/a b;\"c\"\\""#);
+    }
+}
@@ -1,21 +1,185 @@
-use crate::content::Document;
-use pulldown_cmark::{Options, Parser, html};
+use crate::{
+    content::Document,
+    routing::{SlugStyle, slugify_with},
+};
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use std::{collections::HashMap, sync::LazyLock};
 
 pub fn render_html<M>(doc: &Document<M>) -> String {
+    render_html_with(doc, SlugStyle::default())
+}
+
+/// Renders Markdown to HTML, giving every heading without an explicit
+/// `{#id}` an anchor slugified with `slug_style`.
+pub fn render_html_with<M>(doc: &Document<M>, slug_style: SlugStyle) -> String {
+    render_events(doc, slug_style, None)
+}
+
+pub fn render_html_sanitized<M>(doc: &Document<M>, slug_style: SlugStyle) -> String {
+    sanitize(&render_html_with(doc, slug_style))
+}
+
+/// Renders a page body like [`render_html_sanitized`]. With a `base`, the
+/// site-relative URL of the directory a bundle's files are published in,
+/// relative link and image URLs are resolved against it.
+pub fn render_page_html<M>(doc: &Document<M>, slug_style: SlugStyle, base: Option<&str>) -> String {
+    sanitize(&render_events(doc, slug_style, base))
+}
+
+fn render_events<M>(doc: &Document<M>, slug_style: SlugStyle, base: Option<&str>) -> String {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_TABLES);
     options.insert(Options::ENABLE_FOOTNOTES);
     options.insert(Options::ENABLE_STRIKETHROUGH);
     options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
 
-    let parser = Parser::new_ext(&doc.body, options);
+    let mut events: Vec<Event> = Parser::new_ext(&doc.body, options).collect();
+    add_heading_ids(&mut events, slug_style);
+    if let Some(base) = base {
+        resolve_links(&mut events, base);
+    }
 
     let mut html_output = String::with_capacity(doc.body.len() * 3 / 2);
-    html::push_html(&mut html_output, parser);
+    html::push_html(&mut html_output, events.into_iter());
     html_output
 }
 
-pub fn render_html_sanitized<M>(doc: &Document<M>) -> String {
-    let html = render_html(doc);
-    ammonia::clean(&html).to_string()
+/// Renders a Markdown snippet that is not a page, e.g. for the `markdown`
+/// template filter. The output is sanitized like page bodies.
+pub fn render_markdown(text: &str, slug_style: SlugStyle) -> String {
+    let doc: Document<()> = Document {
+        path: Default::default(),
+        front_matter: None,
+        body: text.to_string(),
+    };
+    render_html_sanitized(&doc, slug_style)
+}
+
+fn sanitize(html: &str) -> String {
+    static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+        let mut builder = ammonia::Builder::default();
+        for h in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+            builder.add_tag_attributes(h, &["id"]);
+        }
+        builder
+    });
+    SANITIZER.clean(html).to_string()
+}
+
+fn add_heading_ids(events: &mut [Event], slug_style: SlugStyle) {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut i = 0;
+    while i < events.len() {
+        let Event::Start(Tag::Heading { id: None, .. }) = &events[i] else {
+            i += 1;
+            continue;
+        };
+
+        let mut text = String::new();
+        let mut j = i + 1;
+        while j < events.len() && !matches!(events[j], Event::End(TagEnd::Heading(_))) {
+            if let Event::Text(t) | Event::Code(t) = &events[j] {
+                text.push_str(t);
+            }
+            j += 1;
+        }
+
+        let base = slugify_with(&text, slug_style);
+        let n = seen.entry(base.clone()).or_insert(0);
+        let anchor = if *n == 0 { base } else { format!("{base}-{n}") };
+        *n += 1;
+
+        if let Event::Start(Tag::Heading { id, .. }) = &mut events[i] {
+            *id = Some(CowStr::from(anchor));
+        }
+        i = j;
+    }
+}
+
+fn resolve_links(events: &mut [Event], base: &str) {
+    for event in events {
+        if let Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) = event
+            && let Some(url) = resolve_relative(base, dest_url)
+        {
+            *dest_url = CowStr::from(url);
+        }
+    }
+}
+
+/// `url` joined onto the directory URL `base`, or `None` if it is not a
+/// relative path (absolute, with a scheme, or only a fragment or query).
+pub fn resolve_relative(base: &str, url: &str) -> Option<String> {
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    if path.is_empty() || path.starts_with('/') || path.split('/').next()?.contains(':') {
+        return None;
+    }
+    let mut segments: Vec<&str> = base.split('/').filter(|s| !s.is_empty()).collect();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(seg),
+        }
+    }
+    let mut out = format!("/{}", segments.join("/"));
+    if (path.ends_with('/') || path.ends_with("/.") || path.ends_with("..")) && out != "/" {
+        out.push('/');
+    }
+    out.push_str(suffix);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn doc(body: &str) -> Document<()> {
+        Document {
+            path: PathBuf::from("x.md"),
+            front_matter: None,
+            body: body.into(),
+        }
+    }
+
+    #[test]
+    fn headings_get_unique_slugified_ids() {
+        let html = render_html_sanitized(
+            &doc("# Åsa's `Code`\n## Intro\n## Intro\n### Custom {#mine}\n"),
+            SlugStyle::Ascii,
+        );
+        assert!(html.contains(r#"<h1 id="asas-code">"#));
+        assert!(html.contains(r#"<h2 id="intro">"#));
+        assert!(html.contains(r#"<h2 id="intro-1">"#));
+        assert!(html.contains(r#"<h3 id="mine">"#));
+    }
+
+    #[test]
+    fn relative_links_resolve_against_the_base() {
+        let html = render_page_html(
+            &doc("![a](photo.jpg) [b](../other/#top) [c](/abs) [d](https://x.y/) [e](#here)"),
+            SlugStyle::Ascii,
+            Some("/posts/trip/"),
+        );
+        assert!(html.contains(r#"src="/posts/trip/photo.jpg""#));
+        assert!(html.contains(r#"href="/posts/other/#top""#));
+        assert!(html.contains(r#"href="/abs""#));
+        assert!(html.contains(r#"href="https://x.y/""#));
+        assert!(html.contains(r##"href="#here""##));
+        assert_eq!(
+            resolve_relative("/", "./a/../b.png?v=1"),
+            Some("/b.png?v=1".into())
+        );
+        assert_eq!(resolve_relative("/a/", "mailto:x@y.z"), None);
+    }
+
+    #[test]
+    fn heading_ids_follow_slug_style() {
+        let html = render_html_with(&doc("# Åsa Öberg\n"), SlugStyle::Unicode);
+        assert!(html.contains(r#"<h1 id="åsa-öberg">"#));
+    }
 }
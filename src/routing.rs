@@ -1,19 +1,61 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs, io,
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 use walkdir::WalkDir;
 
-use crate::content::{Document, PageMeta};
+use crate::{
+    cache::{self, CopiedAsset},
+    config::SiteConfig,
+    content::{Document, PageMeta},
+    ignores::IgnoreRules,
+    minify::minify_html,
+};
+
+/// How titles, file names and tags are turned into URL segments and anchors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugStyle {
+    /// Transliterate to lowercase ASCII (`Åsa` → `asa`, `ß` → `ss`).
+    #[default]
+    Ascii,
+    /// Keep Unicode letters and digits, lowercased.
+    Unicode,
+    /// Keep the text as written, only replacing whitespace and URL-reserved characters.
+    Keep,
+}
 
 pub fn slugify(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
+    slugify_with(s, SlugStyle::Ascii)
+}
+
+pub fn slugify_with(s: &str, style: SlugStyle) -> String {
+    let text = match style {
+        SlugStyle::Ascii => deunicode::deunicode(s),
+        SlugStyle::Unicode | SlugStyle::Keep => s.to_string(),
+    };
+    let chars: Box<dyn Iterator<Item = char>> = match style {
+        SlugStyle::Keep => Box::new(text.chars()),
+        _ => Box::new(text.chars().flat_map(char::to_lowercase)),
+    };
+
+    let mut out = String::with_capacity(text.len());
     let mut dash = false;
-    for ch in s.chars().flat_map(|c| c.to_lowercase()) {
-        if ch.is_ascii_alphanumeric() {
+    for ch in chars {
+        let keep = match style {
+            SlugStyle::Ascii => ch.is_ascii_alphanumeric(),
+            SlugStyle::Unicode => ch.is_alphanumeric(),
+            SlugStyle::Keep => !ch.is_whitespace() && !ch.is_control() && !is_reserved(ch),
+        };
+        if keep && ch != '-' {
             out.push(ch);
             dash = false;
-        } else if matches!(ch, ' ' | '-' | '_' | '.') && !dash && !out.is_empty() {
+        } else if (ch.is_whitespace() || matches!(ch, '-' | '_' | '.')) && !dash && !out.is_empty()
+        {
             out.push('-');
             dash = true;
         }
@@ -28,37 +70,191 @@ pub fn slugify(s: &str) -> String {
     }
 }
 
+/// Characters that would change the meaning of a URL path segment.
+fn is_reserved(ch: char) -> bool {
+    matches!(
+        ch,
+        '/' | '\\'
+            | '?'
+            | '#'
+            | '%'
+            | '"'
+            | '<'
+            | '>'
+            | '\''
+            | '`'
+            | '|'
+            | '^'
+            | '{'
+            | '}'
+            | '['
+            | ']'
+    )
+}
+
+/// Where a page is written: its front matter `path` if set, `index` and
+/// `_index` files in place, otherwise the permalink pattern of its directory
+/// (default `/:path/`).
 pub fn out_path_for(
-    src_root: &Path,
-    out_root: &Path,
+    cfg: &SiteConfig,
     md_path: &Path,
     doc: &Document<PageMeta>,
-) -> PathBuf {
-    let rel = md_path.strip_prefix(src_root).unwrap_or(md_path);
+) -> io::Result<PathBuf> {
+    let rel = md_path.strip_prefix(&cfg.src_dir).unwrap_or(md_path);
+    let meta = doc.front_matter.as_ref();
 
-    if rel.file_stem().and_then(|s| s.to_str()) == Some("index") {
-        let mut dest = out_root.join(rel);
-        dest.set_extension("html");
-        return dest;
+    if let Some(path) = meta.and_then(|m| m.path.as_deref()) {
+        return Ok(out_path_for_url(&cfg.out_dir, path, cfg.ugly_urls));
     }
 
+    if matches!(
+        rel.file_stem().and_then(|s| s.to_str()),
+        Some("index" | "_index")
+    ) {
+        return Ok(cfg.out_dir.join(rel).with_file_name("index.html"));
+    }
+
+    let parent = rel.parent().unwrap_or_else(|| Path::new(""));
+    let pattern = permalink_for(&cfg.permalinks, parent).unwrap_or("/:path/");
+    let url = expand_permalink(pattern, rel, meta, cfg.slugify)?;
+    Ok(out_path_for_url(&cfg.out_dir, &url, cfg.ugly_urls))
+}
+
+/// The pattern of the most specific configured directory containing `dir`.
+fn permalink_for<'a>(permalinks: &'a BTreeMap<String, String>, dir: &Path) -> Option<&'a str> {
+    permalinks
+        .iter()
+        .filter(|(section, _)| dir.starts_with(section.trim_matches('/')))
+        .max_by_key(|(section, _)| Path::new(section.trim_matches('/')).components().count())
+        .map(|(_, pattern)| pattern.as_str())
+}
+
+/// Expands `:year`, `:month`, `:day`, `:slug`, `:title`, `:section` and
+/// `:path` (directory plus slug) in a permalink pattern.
+fn expand_permalink(
+    pattern: &str,
+    rel: &Path,
+    meta: Option<&PageMeta>,
+    style: SlugStyle,
+) -> io::Result<String> {
     let stem = rel
         .file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("untitled");
-    let fm_slug = doc
-        .front_matter
-        .as_ref()
-        .and_then(|m| m.slug.as_deref())
-        .unwrap_or(stem);
-    let slug = slugify(fm_slug);
-
     let parent = rel.parent().unwrap_or_else(|| Path::new(""));
-    out_root.join(parent).join(slug).join("index.html")
+    let slug = slugify_with(meta.and_then(|m| m.slug.as_deref()).unwrap_or(stem), style);
+    let date = meta.and_then(|m| m.date.as_deref()).and_then(date_parts);
+    let needs_date = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("permalink `{pattern}` needs a `date: YYYY-MM-DD` in front matter"),
+        )
+    };
+
+    let mut out = String::with_capacity(pattern.len() + slug.len());
+    let mut rest = pattern;
+    while let Some(pos) = rest.find(':') {
+        out.push_str(&rest[..pos]);
+        let name_len = rest[pos + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - pos - 1);
+        let name = &rest[pos + 1..pos + 1 + name_len];
+        rest = &rest[pos + 1 + name_len..];
+
+        match name {
+            "year" => out.push_str(date.ok_or_else(needs_date)?.0),
+            "month" => out.push_str(date.ok_or_else(needs_date)?.1),
+            "day" => out.push_str(date.ok_or_else(needs_date)?.2),
+            "slug" => out.push_str(&slug),
+            "title" => out.push_str(&slugify_with(
+                meta.and_then(|m| m.title.as_deref()).unwrap_or(stem),
+                style,
+            )),
+            "section" => {
+                if let Some(first) = parent.components().next() {
+                    out.push_str(&first.as_os_str().to_string_lossy());
+                }
+            }
+            "path" => {
+                let dir = parent.to_string_lossy().replace('\\', "/");
+                if !dir.is_empty() {
+                    out.push_str(&dir);
+                    out.push('/');
+                }
+                out.push_str(&slug);
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("permalink `{pattern}`: unknown placeholder `:{other}`"),
+                ));
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn date_parts(date: &str) -> Option<(&str, &str, &str)> {
+    let ymd = date.get(..10)?;
+    let mut parts = ymd.split('-');
+    let (y, m, d) = (parts.next()?, parts.next()?, parts.next()?);
+    let digits = |s: &str, n: usize| s.len() == n && s.bytes().all(|b| b.is_ascii_digit());
+    (digits(y, 4) && digits(m, 2) && digits(d, 2)).then_some((y, m, d))
+}
+
+/// Maps a site URL onto the file that serves it. Directory-style URLs become
+/// `index.html` files, or `<name>.html` with `ugly_urls`.
+pub fn out_path_for_url(out_root: &Path, url: &str, ugly_urls: bool) -> PathBuf {
+    let segments: Vec<&str> = url
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .collect();
+    let Some((last, dirs)) = segments.split_last() else {
+        return out_root.join("index.html");
+    };
+
+    let mut dest = out_root.to_path_buf();
+    dest.extend(dirs);
+    if !url.ends_with('/') && last.contains('.') {
+        dest.push(last);
+    } else if ugly_urls {
+        dest.push(format!("{last}.html"));
+    } else {
+        dest.push(last);
+        dest.push("index.html");
+    }
+    dest
 }
 
-pub fn copy_static_assets(src_root: &Path, out_root: &Path) -> io::Result<()> {
-    for entry in WalkDir::new(src_root) {
+/// Every file `copy_static_assets` would copy, as `(source, destination)`:
+/// the theme's `static/` files, overridden file by file by the site's own.
+/// Ignored and hidden files are left out.
+pub fn static_assets(
+    src_root: &Path,
+    out_root: &Path,
+    theme_dir: Option<&Path>,
+    ignores: &IgnoreRules,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut assets: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    if let Some(static_dir) = theme_dir.map(|d| d.join("static")) {
+        let walker = WalkDir::new(&static_dir)
+            .into_iter()
+            .filter_entry(|e| !ignores.is_ignored(e.path(), e.file_type().is_dir()));
+        for entry in walker.filter_map(Result::ok) {
+            if let Ok(rel) = entry.path().strip_prefix(&static_dir)
+                && entry.file_type().is_file()
+            {
+                assets.insert(out_root.join(rel), entry.path().to_path_buf());
+            }
+        }
+    }
+
+    // An output directory nested in the source tree is not a source of assets.
+    let walker = WalkDir::new(src_root).into_iter().filter_entry(|e| {
+        e.path() != out_root && !ignores.is_ignored(e.path(), e.file_type().is_dir())
+    });
+    for entry in walker {
         let entry = match entry {
             Ok(e) => e,
             Err(e) => {
@@ -76,11 +272,11 @@ pub fn copy_static_assets(src_root: &Path, out_root: &Path) -> io::Result<()> {
             Err(_) => continue,
         };
 
-        if rel
-            .components()
-            .next()
-            .is_some_and(|c| c.as_os_str() == "templates")
-        {
+        if rel.components().next().is_some_and(|c| {
+            ["templates", "themes", "data"]
+                .iter()
+                .any(|d| c.as_os_str() == *d)
+        }) {
             continue;
         }
         if rel.file_name().is_some_and(|n| n == "site.toml") {
@@ -93,15 +289,204 @@ pub fn copy_static_assets(src_root: &Path, out_root: &Path) -> io::Result<()> {
             continue;
         }
 
-        let dest = out_root.join(rel);
-        if let Some(parent) = dest.parent() {
-            fs::create_dir_all(parent)?;
+        assets.insert(out_root.join(rel), entry.into_path());
+    }
+    assets.into_iter().map(|(dest, src)| (src, dest)).collect()
+}
+
+/// Writes a generated HTML file, minified when `minify_html` is on. Every
+/// HTML output goes through here.
+pub fn write_html(cfg: &SiteConfig, path: &Path, html: &str) -> io::Result<()> {
+    if cfg.minify_html {
+        fs::write(path, minify_html(html))
+    } else {
+        fs::write(path, html)
+    }
+}
+
+/// Copies `(source, destination)` pairs as listed by `static_assets`,
+/// possibly with fingerprinted destinations, in parallel. Files `prev` shows
+/// to be up to date are left alone, and files it lists that are no longer
+/// among `assets` are deleted. Returns the record for the next build.
+pub fn copy_static_assets(
+    cfg: &SiteConfig,
+    assets: &[(PathBuf, PathBuf)],
+    prev: &HashMap<String, CopiedAsset>,
+) -> io::Result<HashMap<String, CopiedAsset>> {
+    let out_root = &cfg.out_dir;
+    let copied = assets
+        .par_iter()
+        .map(|(src, dest)| {
+            let key = dest
+                .strip_prefix(out_root)
+                .unwrap_or(dest)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let entry = place_asset(src, dest, prev.get(&key), cfg.assets.hardlink)?;
+            Ok((key, entry))
+        })
+        .collect::<io::Result<HashMap<_, _>>>()?;
+
+    // Outputs that were copied last time but have no source any more. The
+    // image cache and build cache are never in the record.
+    for key in prev.keys().filter(|k| !copied.contains_key(*k)) {
+        let dest = out_root.join(key);
+        match fs::remove_file(&dest) {
+            Ok(()) => remove_empty_dirs(out_root, &dest),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(copied)
+}
+
+/// Puts `src` at `dest` unless the previous build already did and neither
+/// side has changed since: same source, same size, and the same mtime or,
+/// failing that, the same content hash.
+fn place_asset(
+    src: &Path,
+    dest: &Path,
+    prev: Option<&CopiedAsset>,
+    hardlink: bool,
+) -> io::Result<CopiedAsset> {
+    let meta = fs::metadata(src)?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as u64);
+    let dest_size = fs::metadata(dest).ok().map(|m| m.len());
+
+    if let Some(prev) = prev
+        && prev.source == src
+        && prev.size == meta.len()
+        && dest_size == Some(meta.len())
+    {
+        if prev.mtime == mtime {
+            return Ok(prev.clone());
+        }
+        let hash = cache::file_hash(src)?;
+        if hash == prev.hash {
+            return Ok(CopiedAsset {
+                mtime,
+                ..prev.clone()
+            });
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Never write through an existing file: it may be a hard link to a
+    // source.
+    match fs::remove_file(dest) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    // `fs::copy` clones the file on filesystems that support it.
+    if !hardlink || fs::hard_link(src, dest).is_err() {
+        fs::copy(src, dest)?;
+    }
+    Ok(CopiedAsset {
+        source: src.to_path_buf(),
+        size: meta.len(),
+        mtime,
+        hash: cache::file_hash(src)?,
+    })
+}
+
+/// Deletes the files in `prev`, relative to `out_root`, that nothing claims
+/// any more, along with the directories this leaves empty.
+pub fn remove_unclaimed(
+    out_root: &Path,
+    prev: &BTreeSet<String>,
+    claims: &OutputClaims,
+) -> io::Result<()> {
+    let claimed: HashSet<&Path> = claims.paths().collect();
+    for key in prev {
+        let path = out_root.join(key);
+        if claimed.contains(path.as_path()) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => remove_empty_dirs(out_root, &path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
         }
-        fs::copy(p, &dest)?;
     }
     Ok(())
 }
 
+/// Removes the directories between `path` and `root` that are now empty.
+fn remove_empty_dirs(root: &Path, path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Output files and the sources that want to write them.
+#[derive(Debug, Default)]
+pub struct OutputClaims {
+    claims: BTreeMap<PathBuf, Vec<String>>,
+    /// Directories whose files are generated during rendering, so nothing
+    /// else may write into them.
+    dirs: BTreeMap<PathBuf, String>,
+}
+
+impl OutputClaims {
+    pub fn claim(&mut self, out_path: PathBuf, source: impl Into<String>) {
+        self.claims.entry(out_path).or_default().push(source.into());
+    }
+
+    pub fn claim_dir(&mut self, dir: PathBuf, source: impl Into<String>) {
+        self.dirs.insert(dir, source.into());
+    }
+
+    /// Every claimed output file.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.claims.keys().map(PathBuf::as_path)
+    }
+
+    /// Fails when any output is claimed twice, listing every URL in conflict
+    /// together with all of its claimants.
+    pub fn check(&self, out_root: &Path) -> io::Result<()> {
+        let mut msg = String::new();
+        for (path, sources) in &self.claims {
+            let url = url_for_out_path(out_root, path);
+            if sources.len() > 1 {
+                msg.push_str(&format!("\n  {url} <- {}", sources.join(", ")));
+            }
+            if let Some((_, owner)) = self.dirs.iter().find(|(dir, _)| path.starts_with(dir)) {
+                msg.push_str(&format!("\n  {url} <- {}, {owner}", sources.join(", ")));
+            }
+        }
+        if msg.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("several sources write the same URL:{msg}"),
+            ))
+        }
+    }
+}
+
+/// Joins a site-relative URL onto `base_url`. URLs that already carry a
+/// scheme are returned unchanged.
+pub fn absolute_url(base_url: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        url.trim_start_matches('/')
+    )
+}
+
 pub fn url_for_out_path(out_root: &Path, out_path: &Path) -> String {
     let rel: PathBuf = out_path
         .strip_prefix(out_root)
@@ -127,7 +512,6 @@ pub fn url_for_out_path(out_root: &Path, out_path: &Path) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::content::{Document, PageMeta};
 
     #[test]
     fn slugify_basic() {
@@ -136,41 +520,163 @@ mod tests {
         assert_eq!(slugify(""), "untitled");
     }
 
+    fn cfg() -> SiteConfig {
+        SiteConfig {
+            src_dir: "/s".into(),
+            out_dir: "/o".into(),
+            ..Default::default()
+        }
+    }
+
+    fn doc(path: &str, meta: Option<PageMeta>) -> Document<PageMeta> {
+        Document {
+            path: path.into(),
+            front_matter: meta,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn slugify_transliterates_by_default() {
+        assert_eq!(slugify("Åsa Öberg"), "asa-oberg");
+        assert_eq!(slugify("Straße"), "strasse");
+        assert_eq!(slugify("مرحبا بالعالم"), "mrhb-bllm");
+    }
+
+    #[test]
+    fn slugify_unicode_and_keep_styles() {
+        assert_eq!(slugify_with("Åsa Öberg!", SlugStyle::Unicode), "åsa-öberg");
+        assert_eq!(
+            slugify_with("مرحبا بالعالم", SlugStyle::Unicode),
+            "مرحبا-بالعالم"
+        );
+        assert_eq!(slugify_with("C# / .NET", SlugStyle::Unicode), "c-net");
+        assert_eq!(
+            slugify_with("Åsa's C# Notes", SlugStyle::Keep),
+            "Åsas-C-Notes"
+        );
+        assert_eq!(slugify_with("???", SlugStyle::Keep), "untitled");
+    }
+
     #[test]
     fn out_path_for_index_and_post() {
-        let src = Path::new("/s");
-        let out = Path::new("/o");
+        let cfg = cfg();
 
         let idx = Path::new("/s/index.md");
-        let doc_index = Document::<PageMeta> {
-            path: idx.into(),
-            front_matter: None,
-            body: String::new(),
-        };
-        let p = out_path_for(src, out, idx, &doc_index);
+        let p = out_path_for(&cfg, idx, &doc("/s/index.md", None)).unwrap();
         assert_eq!(p, Path::new("/o/index.html"));
 
+        let section = Path::new("/s/posts/_index.md");
+        let p = out_path_for(&cfg, section, &doc("/s/posts/_index.md", None)).unwrap();
+        assert_eq!(p, Path::new("/o/posts/index.html"));
+
         let post = Path::new("/s/posts/first.md");
-        let doc_post = Document::<PageMeta> {
-            path: post.into(),
-            front_matter: None,
-            body: String::new(),
-        };
-        let p2 = out_path_for(src, out, post, &doc_post);
+        let p2 = out_path_for(&cfg, post, &doc("/s/posts/first.md", None)).unwrap();
         assert_eq!(p2, Path::new("/o/posts/first/index.html"));
 
-        let with_slug = Document::<PageMeta> {
-            path: post.into(),
-            front_matter: Some(PageMeta {
+        let with_slug = doc(
+            "/s/posts/first.md",
+            Some(PageMeta {
                 slug: Some("custom-slug".into()),
                 ..Default::default()
             }),
-            body: String::new(),
-        };
-        let p3 = out_path_for(src, out, post, &with_slug);
+        );
+        let p3 = out_path_for(&cfg, post, &with_slug).unwrap();
         assert_eq!(p3, Path::new("/o/posts/custom-slug/index.html"));
     }
 
+    #[test]
+    fn out_path_for_permalink_patterns() {
+        let mut cfg = cfg();
+        cfg.permalinks
+            .insert("posts".into(), "/:year/:month/:slug/".into());
+        cfg.permalinks.insert("docs".into(), "/:path/".into());
+        cfg.permalinks
+            .insert("docs/api".into(), "/reference/:slug/".into());
+
+        let dated = doc(
+            "/s/posts/Hello World.md",
+            Some(PageMeta {
+                date: Some("2024-05-17".into()),
+                ..Default::default()
+            }),
+        );
+        let p = out_path_for(&cfg, Path::new("/s/posts/Hello World.md"), &dated).unwrap();
+        assert_eq!(p, Path::new("/o/2024/05/hello-world/index.html"));
+
+        let undated = doc("/s/posts/x.md", None);
+        let err = out_path_for(&cfg, Path::new("/s/posts/x.md"), &undated).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let guide = doc("/s/docs/guide/intro.md", None);
+        let p = out_path_for(&cfg, Path::new("/s/docs/guide/intro.md"), &guide).unwrap();
+        assert_eq!(p, Path::new("/o/docs/guide/intro/index.html"));
+
+        let api = doc("/s/docs/api/client.md", None);
+        let p = out_path_for(&cfg, Path::new("/s/docs/api/client.md"), &api).unwrap();
+        assert_eq!(p, Path::new("/o/reference/client/index.html"));
+    }
+
+    #[test]
+    fn out_path_for_front_matter_path_and_ugly_urls() {
+        let mut cfg = cfg();
+        let moved = doc(
+            "/s/posts/first.md",
+            Some(PageMeta {
+                path: Some("/about/me/".into()),
+                ..Default::default()
+            }),
+        );
+        let p = out_path_for(&cfg, Path::new("/s/posts/first.md"), &moved).unwrap();
+        assert_eq!(p, Path::new("/o/about/me/index.html"));
+
+        cfg.ugly_urls = true;
+        let p = out_path_for(&cfg, Path::new("/s/posts/first.md"), &moved).unwrap();
+        assert_eq!(p, Path::new("/o/about/me.html"));
+        assert_eq!(url_for_out_path(Path::new("/o"), &p), "/about/me.html");
+
+        let post = doc("/s/posts/first.md", None);
+        let p = out_path_for(&cfg, Path::new("/s/posts/first.md"), &post).unwrap();
+        assert_eq!(p, Path::new("/o/posts/first.html"));
+
+        let escaping = doc(
+            "/s/a.md",
+            Some(PageMeta {
+                path: Some("/../../etc/feed.xml".into()),
+                ..Default::default()
+            }),
+        );
+        let p = out_path_for(&cfg, Path::new("/s/a.md"), &escaping).unwrap();
+        assert_eq!(p, Path::new("/o/etc/feed.xml"));
+    }
+
+    #[test]
+    fn claims_report_every_source_of_a_collision() {
+        let out = Path::new("/o");
+        let mut claims = OutputClaims::default();
+        claims.claim(
+            out.join("posts/hello-world/index.html"),
+            "posts/Hello World.md",
+        );
+        claims.claim(
+            out.join("posts/hello-world/index.html"),
+            "posts/hello-world.md",
+        );
+        claims.claim(out.join("posts/other/index.html"), "posts/other.md");
+        claims.claim(out.join("tags/index.html"), "tag index");
+        claims.claim(out.join("tags/index.html"), "tags.md");
+        claims.claim_dir(out.join("processed_images"), "processed images");
+        claims.claim(out.join("processed_images/a.png"), "processed_images/a.png");
+
+        let err = claims.check(out).unwrap_err().to_string();
+        assert!(err.contains("/posts/hello-world/ <- posts/Hello World.md, posts/hello-world.md"));
+        assert!(err.contains("/tags/ <- tag index, tags.md"));
+        assert!(
+            err.contains("/processed_images/a.png <- processed_images/a.png, processed images")
+        );
+        assert!(!err.contains("other"));
+    }
+
     #[test]
     fn url_for_out_path_index_rules() {
         assert_eq!(
@@ -186,4 +692,55 @@ mod tests {
             "/raw.html"
         );
     }
+
+    #[test]
+    fn copies_only_changed_assets_and_removes_deleted_ones() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cfg = cfg();
+        cfg.out_dir = dir.path().join("out");
+        let src = dir.path().join("a.txt");
+        let dest = cfg.out_dir.join("css/a.txt");
+        fs::write(&src, "abcd")?;
+        let assets = vec![(src.clone(), dest.clone())];
+
+        let first = copy_static_assets(&cfg, &assets, &HashMap::new())?;
+        assert_eq!(fs::read_to_string(&dest)?, "abcd");
+        assert_eq!(first["css/a.txt"].hash, cache::file_hash(&src)?);
+
+        // Unchanged sources are not copied again.
+        fs::write(&dest, "wxyz")?;
+        let second = copy_static_assets(&cfg, &assets, &first)?;
+        assert_eq!(fs::read_to_string(&dest)?, "wxyz");
+        assert_eq!(second, first);
+
+        fs::write(&src, "abcde")?;
+        let third = copy_static_assets(&cfg, &assets, &second)?;
+        assert_eq!(fs::read_to_string(&dest)?, "abcde");
+
+        let last = copy_static_assets(&cfg, &[], &third)?;
+        assert!(last.is_empty());
+        assert!(!dest.exists());
+        assert!(!dest.parent().unwrap().exists());
+        assert!(cfg.out_dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn hard_linked_assets_are_replaced_not_written_through() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cfg = cfg();
+        cfg.out_dir = dir.path().join("out");
+        cfg.assets.hardlink = true;
+        let src = dir.path().join("a.txt");
+        let other = dir.path().join("b.txt");
+        let dest = cfg.out_dir.join("a.txt");
+        fs::write(&src, "a")?;
+        fs::write(&other, "bb")?;
+
+        let prev = copy_static_assets(&cfg, &[(src.clone(), dest.clone())], &HashMap::new())?;
+        copy_static_assets(&cfg, &[(other, dest.clone())], &prev)?;
+        assert_eq!(fs::read_to_string(&dest)?, "bb");
+        assert_eq!(fs::read_to_string(&src)?, "a");
+        Ok(())
+    }
 }
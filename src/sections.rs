@@ -0,0 +1,162 @@
+//! Listing pages of sections: a directory with an `_index.md` lists its
+//! other pages, split into pages of `paginate` items as set in the
+//! `_index.md` front matter or, failing that, `[pagination]`.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::SiteConfig,
+    content::PageMeta,
+    context::SectionView,
+    paginate::{PageInfo, PaginationView, paginate},
+    redirects::Redirect,
+    routing::out_path_for_url,
+    taxonomy::PageSummary,
+};
+
+/// `section` on one page of a section listing: the section with the pages
+/// on that page only.
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionListing {
+    pub path: String,
+    pub title: String,
+    pub url: String,
+    pub pages: Vec<PageSummary>,
+    pub pagination: PaginationView,
+}
+
+/// Every file a section page is rendered to.
+#[derive(Debug, Clone)]
+pub struct SectionPages {
+    /// The listing pages in order, the first at the `_index.md` output.
+    pub pages: Vec<(PathBuf, SectionListing)>,
+    /// `/<section>/page/1/`, pointing back at the first page.
+    pub redirect: Redirect,
+}
+
+/// Splits `section`, headed by an `_index.md` with front matter `meta`
+/// written at `out_path`, into its listing pages.
+pub fn section_pages(
+    cfg: &SiteConfig,
+    meta: &PageMeta,
+    section: &SectionView,
+    out_path: &Path,
+) -> SectionPages {
+    let segment = meta
+        .paginate_path
+        .as_deref()
+        .unwrap_or(&cfg.pagination.path)
+        .trim_matches('/');
+    let base = section.url.trim_end_matches('/');
+    let url = |i: usize| {
+        if i == 0 {
+            section.url.clone()
+        } else {
+            format!("{base}/{segment}/{}/", i + 1)
+        }
+    };
+    let per_page = match meta.paginate.unwrap_or(cfg.pagination.per_page) {
+        0 => section.pages.len().max(1),
+        n => n,
+    };
+    let mut chunks = paginate(&section.pages, per_page);
+    // An empty section still gets its page.
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let total_pages = chunks.len();
+
+    let pages = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let info = PageInfo {
+                index: i,
+                total_pages,
+            };
+            let listing = SectionListing {
+                path: section.path.clone(),
+                title: section.title.clone(),
+                url: url(i),
+                pages: chunk.to_vec(),
+                pagination: PaginationView::new(
+                    info,
+                    per_page,
+                    section.pages.len(),
+                    cfg.pagination.window,
+                    url,
+                ),
+            };
+            let path = match i {
+                0 => out_path.to_path_buf(),
+                _ => out_path_for_url(&cfg.out_dir, &listing.url, false),
+            };
+            (path, listing)
+        })
+        .collect();
+    let redirect = Redirect::new(&cfg.out_dir, &format!("{base}/{segment}/1/"), out_path);
+    SectionPages { pages, redirect }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PaginationConfig;
+
+    fn section(n: usize) -> SectionView {
+        SectionView {
+            path: "posts".into(),
+            title: "Blog".into(),
+            url: "/posts/".into(),
+            pages: (0..n)
+                .map(|i| PageSummary {
+                    title: format!("p{i}"),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn front_matter_overrides_pagination_defaults() {
+        let cfg = SiteConfig {
+            out_dir: "/o".into(),
+            pagination: PaginationConfig {
+                per_page: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let meta = PageMeta {
+            paginate: Some(2),
+            paginate_path: Some("p".into()),
+            ..Default::default()
+        };
+        let out = Path::new("/o/posts/index.html");
+
+        let split = section_pages(&cfg, &meta, &section(5), out);
+        let paths: Vec<&Path> = split.pages.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/o/posts/index.html"),
+                Path::new("/o/posts/p/2/index.html"),
+                Path::new("/o/posts/p/3/index.html"),
+            ]
+        );
+        let last = &split.pages[2].1;
+        assert_eq!(last.pages.len(), 1);
+        assert_eq!(last.pagination.prev_url.as_deref(), Some("/posts/p/2/"));
+        assert_eq!(last.pagination.first_url, "/posts/");
+        assert_eq!(
+            split.redirect.out_path,
+            Path::new("/o/posts/p/1/index.html")
+        );
+        assert_eq!(split.redirect.to, "/posts/");
+
+        let all = section_pages(&cfg, &PageMeta::default(), &section(0), out);
+        assert_eq!(all.pages.len(), 1);
+        assert_eq!(all.pages[0].1.pagination.total, 1);
+    }
+}
@@ -1,56 +1,167 @@
 use serde::Serialize;
-use std::{collections::HashMap, fs, io, path::Path};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    config::SiteConfig,
-    content::Document,
-    paginate::{PageInfo, neighbors, paginate},
-    routing::{slugify, url_for_out_path},
+    config::{IndexSort, SiteConfig, SortBy, TaxonomyConfig},
+    content::PageMeta,
+    context::SiteContext,
+    images,
+    paginate::{PageInfo, PaginationView, paginate},
+    redirects::{Redirect, write_redirect_page},
+    routing::{out_path_for_url, slugify_with, url_for_out_path, write_html},
     templates::Templates,
 };
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Default, serde::Serialize)]
 pub struct PageSummary {
     pub title: String,
     pub url: String,
+    /// Source path relative to the content root, with `/` separators.
+    pub path: String,
+    pub date: Option<String>,
+    pub weight: Option<i64>,
     pub tags: Vec<String>,
+    /// Terms of every configured taxonomy other than `tags`.
+    pub taxonomies: BTreeMap<String, Vec<String>>,
+}
+
+impl PageSummary {
+    pub fn terms(&self, taxonomy: &str) -> &[String] {
+        if taxonomy == "tags" {
+            return &self.tags;
+        }
+        self.taxonomies.get(taxonomy).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A taxonomy with all of its terms, as templates see it under `taxonomies`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxonomyListing {
+    pub name: String,
+    pub url: String,
+    pub terms: Vec<TermListing>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermListing {
+    pub name: String,
+    pub url: String,
+    pub count: usize,
+    pub pages: Vec<PageSummary>,
 }
 
 #[derive(Serialize)]
-struct PaginationView {
-    current: usize,
-    total: usize,
-    prev_url: Option<String>,
-    next_url: Option<String>,
+struct TaxonomyView<'a> {
+    name: &'a str,
+    url: String,
+}
+
+/// The pages listed under one term.
+#[derive(Debug)]
+pub struct TermGroup<'a> {
+    /// Spelling shown to readers: the one configured in `synonyms`, else the
+    /// most common one, else the first seen.
+    pub name: String,
+    pub pages: Vec<&'a PageSummary>,
 }
 
-pub fn group_by_tag(pages: &[PageSummary]) -> HashMap<String, Vec<&PageSummary>> {
-    use std::collections::hash_map::Entry;
-    let mut map: HashMap<String, Vec<&PageSummary>> = HashMap::new();
+/// Pages per term key (see [`TaxonomyConfig::term_key`]), in key order so
+/// output is stable between builds.
+pub fn group_by_term<'a>(
+    pages: &'a [PageSummary],
+    tax: &TaxonomyConfig,
+) -> BTreeMap<String, TermGroup<'a>> {
+    // Per key: the pages, and each spelling seen with its use count.
+    type Entry<'a> = (Vec<&'a PageSummary>, Vec<(&'a str, usize)>);
+    let mut map: BTreeMap<String, Entry> = BTreeMap::new();
     for p in pages {
-        for tag in &p.tags {
-            let key = tag.to_lowercase();
-            match map.entry(key) {
-                Entry::Occupied(mut e) => e.get_mut().push(p),
-                Entry::Vacant(v) => {
-                    v.insert(vec![p]);
-                }
+        for term in p.terms(&tax.name) {
+            let (items, spellings) = map.entry(tax.term_key(term)).or_default();
+            items.push(p);
+            match spellings.iter_mut().find(|(s, _)| *s == term) {
+                Some((_, n)) => *n += 1,
+                None => spellings.push((term, 1)),
             }
         }
     }
-    map
+    map.into_iter()
+        .map(|(key, (pages, spellings))| {
+            let name = match tax.configured_name(&key) {
+                Some(name) => name.to_string(),
+                // `max_by_key` keeps the last maximum, so scan in reverse
+                // to let the first seen spelling win ties.
+                None => spellings
+                    .iter()
+                    .rev()
+                    .max_by_key(|(_, n)| *n)
+                    .map_or_else(|| key.clone(), |(s, _)| s.to_string()),
+            };
+            (key, TermGroup { name, pages })
+        })
+        .collect()
 }
 
-pub fn write_tag_pages(
+pub fn group_by_tag(pages: &[PageSummary]) -> BTreeMap<String, TermGroup<'_>> {
+    group_by_term(pages, &TaxonomyConfig::tags())
+}
+
+/// URL slug of every term key. Keys whose slugs coincide, such as `c`, `c#`
+/// and `c++`, keep their own pages: the first in key order gets the plain
+/// slug and the others a numeric suffix (`c-1`, `c-2`).
+fn term_slugs<T>(cfg: &SiteConfig, groups: &BTreeMap<String, T>) -> HashMap<String, String> {
+    let mut slugs = HashMap::new();
+    let mut taken = HashSet::new();
+    let mut clashing = Vec::new();
+    for key in groups.keys() {
+        let slug = slugify_with(key, cfg.slugify);
+        if taken.insert(slug.clone()) {
+            slugs.insert(key.clone(), slug);
+        } else {
+            clashing.push((key, slug));
+        }
+    }
+    for (key, slug) in clashing {
+        let unique = (1..)
+            .map(|n| format!("{slug}-{n}"))
+            .find(|s| !taken.contains(s))
+            .unwrap_or(slug);
+        taken.insert(unique.clone());
+        slugs.insert(key.clone(), unique);
+    }
+    slugs
+}
+
+/// Writes the term pages (and feeds, if enabled) and the index of one
+/// taxonomy. With `only`, term pages are limited to those term
+/// keys; the index is always rewritten, unless there are no terms at all.
+/// Templates the site does not define fall back to the built-in ones.
+/// Returns the processed images each written file uses.
+pub fn write_taxonomy_pages(
     templates: &Templates,
+    site: &SiteContext,
     cfg: &SiteConfig,
+    tax: &TaxonomyConfig,
     out_root: &Path,
     pages: &[PageSummary],
-) -> io::Result<()> {
-    let groups = group_by_tag(pages);
+    only: Option<&HashSet<String>>,
+) -> io::Result<HashMap<PathBuf, BTreeSet<String>>> {
+    let mut written = HashMap::new();
+    let mut groups = group_by_term(pages, tax);
+    if groups.is_empty() {
+        return Ok(written);
+    }
+    let slugs = term_slugs(cfg, &groups);
+    let term_template = templates.resolve(&tax.term_template(), "tag.html");
+    let index_template = templates.resolve(&tax.index_template(), "tags.html");
+    let feed_template = templates.resolve("feed.xml", "feed.xml");
 
     #[derive(Serialize)]
-    struct TagPage<'a> {
+    struct TermPage<'a> {
         name: &'a str,
         pages: Vec<&'a PageSummary>,
         url: String,
@@ -58,20 +169,33 @@ pub fn write_tag_pages(
     }
     #[derive(Serialize)]
     struct TagsIndex<'a> {
-        tags: Vec<TagSummary<'a>>,
+        tags: &'a [TermListing],
     }
     #[derive(Serialize)]
-    struct TagSummary<'a> {
-        name: &'a str,
-        count: usize,
+    struct Feed<'a> {
+        title: &'a str,
         url: String,
+        term_url: String,
+        updated: Option<String>,
+        pages: &'a [&'a PageSummary],
     }
 
-    const PAGE_SIZE: usize = 10;
+    let taxonomy = TaxonomyView {
+        name: &tax.name,
+        url: format!("/{}/", tax.path()),
+    };
 
-    for (tag_name, items) in groups.iter() {
-        let slug = slugify(tag_name);
-        let chunks = paginate(items, PAGE_SIZE);
+    for (key, group) in groups.iter_mut() {
+        if only.is_some_and(|only| !only.contains(key)) {
+            continue;
+        }
+        let term = group.name.as_str();
+        let items = &mut group.pages;
+        sort_pages(items, tax.sort);
+        let slug = &slugs[key];
+        let url = |i| term_page_url(cfg, tax, slug, i);
+        let per_page = page_size(cfg, tax, items.len());
+        let chunks = paginate(items, per_page);
         let total_pages = chunks.len();
 
         for (i, chunk) in chunks.iter().enumerate() {
@@ -79,107 +203,269 @@ pub fn write_tag_pages(
                 index: i,
                 total_pages,
             };
-            let (prev, next) = neighbors(info);
-
-            let out_path = if i == 0 {
-                out_root.join("tags").join(&slug).join("index.html")
-            } else {
-                out_root
-                    .join("tags")
-                    .join(&slug)
-                    .join("page")
-                    .join((i + 1).to_string())
-                    .join("index.html")
-            };
+            let out_path = term_page_path(cfg, tax, out_root, slug, i);
             if let Some(parent) = out_path.parent() {
                 fs::create_dir_all(parent)?;
             }
 
-            let pagination = PaginationView {
-                current: i + 1,
-                total: total_pages,
-                prev_url: prev.map(|p| tag_page_url(&slug, p)),
-                next_url: next.map(|n| tag_page_url(&slug, n)),
-            };
-
-            let tag_vm = TagPage {
-                name: tag_name,
+            let term_vm = TermPage {
+                name: term,
                 pages: chunk.to_vec(),
-                url: tag_page_url(&slug, i),
-                pagination,
+                url: url(i),
+                pagination: PaginationView::new(
+                    info,
+                    per_page,
+                    items.len(),
+                    cfg.pagination.window,
+                    url,
+                ),
             };
 
-            let html = render_tag(templates, cfg, &tag_vm)?;
-            fs::write(out_path, html)?;
+            let mut ctx = tera::Context::new();
+            ctx.insert("taxonomy", &taxonomy);
+            ctx.insert("term", &term_vm);
+            if tax.name == "tags" {
+                ctx.insert("tag", &term_vm);
+            }
+            let (html, used) = images::track(|| render(templates, site, &term_template, ctx));
+            write_html(cfg, &out_path, &html?)?;
+            written.insert(out_path, used);
         }
-    }
+        write_redirect_page(cfg, &first_page_redirect(cfg, tax, out_root, slug))?;
 
-    let mut all: Vec<_> = groups
-        .iter()
-        .map(|(name, items)| TagSummary {
-            name,
-            count: items.len(),
-            url: format!("/tags/{}/", slugify(name)),
-        })
-        .collect();
-    all.sort_by(|a, b| a.name.cmp(b.name));
+        if tax.feed {
+            let feed = Feed {
+                title: term,
+                url: format!("{}feed.xml", url(0)),
+                term_url: url(0),
+                updated: items
+                    .iter()
+                    .filter_map(|p| p.date.as_deref())
+                    .max()
+                    .map(atom_date),
+                pages: items,
+            };
+            let mut ctx = tera::Context::new();
+            ctx.insert("taxonomy", &taxonomy);
+            ctx.insert("feed", &feed);
+            let (xml, used) = images::track(|| render(templates, site, &feed_template, ctx));
+            let out_path = term_feed_path(out_root, tax, slug);
+            fs::write(&out_path, xml?)?;
+            written.insert(out_path, used);
+        }
+    }
 
-    let index_vm = TagsIndex { tags: all };
-    let html = render_tags_index(templates, cfg, &index_vm)?;
-    let out_path = out_root.join("tags").join("index.html");
+    let all = taxonomy_listing(cfg, tax, pages).terms;
+    let mut ctx = tera::Context::new();
+    ctx.insert("taxonomy", &taxonomy);
+    ctx.insert("terms", &all);
+    if tax.name == "tags" {
+        ctx.insert("tags", &TagsIndex { tags: &all });
+    }
+    let (html, used) = images::track(|| render(templates, site, &index_template, ctx));
+    let out_path = taxonomy_dir(out_root, tax).join("index.html");
     if let Some(parent) = out_path.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::write(out_path, html)?;
-    Ok(())
-}
-
-fn render_tag(templates: &Templates, cfg: &SiteConfig, tag: &impl Serialize) -> io::Result<String> {
-    use tera::Context;
-    let mut ctx = Context::new();
-    ctx.insert("site", cfg);
-    ctx.insert("tag", tag);
-    templates.tera().render("tag.html", &ctx).map_err(to_io)
+    write_html(cfg, &out_path, &html?)?;
+    written.insert(out_path, used);
+    Ok(written)
 }
 
-fn render_tags_index(
+fn render(
     templates: &Templates,
-    cfg: &SiteConfig,
-    tags: &impl Serialize,
+    site: &SiteContext,
+    template: &str,
+    vars: tera::Context,
 ) -> io::Result<String> {
-    use tera::Context;
-    let mut ctx = Context::new();
-    ctx.insert("site", cfg);
-    ctx.insert("tags", tags);
-    templates.tera().render("tags.html", &ctx).map_err(to_io)
+    site.render(templates.tera(), template, vars).map_err(to_io)
 }
 
 fn to_io(e: tera::Error) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, e)
 }
 
-pub fn summarize<M>(
-    _doc: &Document<M>,
+/// Sorts a term's pages, breaking ties by title and URL so the order never
+/// depends on the order pages were collected in.
+pub(crate) fn sort_pages(pages: &mut [&PageSummary], by: SortBy) {
+    pages.sort_by(|a, b| {
+        let primary = match by {
+            // `None` sorts before `Some`, so reversing puts undated pages last.
+            SortBy::Date => b.date.cmp(&a.date),
+            SortBy::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            SortBy::Weight => match (a.weight, b.weight) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        };
+        primary
+            .then_with(|| a.title.cmp(&b.title))
+            .then_with(|| a.url.cmp(&b.url))
+    });
+}
+
+fn sort_index(terms: &mut [TermListing], by: IndexSort) {
+    match by {
+        IndexSort::Name => terms.sort_by(|a, b| cmp_names(&a.name, &b.name)),
+        IndexSort::Count => {
+            terms.sort_by(|a, b| b.count.cmp(&a.count).then(cmp_names(&a.name, &b.name)))
+        }
+    }
+}
+
+fn cmp_names(a: &str, b: &str) -> Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+/// Zero means "no pagination": every page on a single term page.
+fn page_size(cfg: &SiteConfig, tax: &TaxonomyConfig, items: usize) -> usize {
+    match tax.per_page(&cfg.pagination) {
+        0 => items.max(1),
+        n => n,
+    }
+}
+
+/// Every term of `tax` with its sorted pages, in index order.
+pub fn taxonomy_listing(
+    cfg: &SiteConfig,
+    tax: &TaxonomyConfig,
+    pages: &[PageSummary],
+) -> TaxonomyListing {
+    let groups = group_by_term(pages, tax);
+    let slugs = term_slugs(cfg, &groups);
+    let mut terms: Vec<TermListing> = groups
+        .into_iter()
+        .map(|(key, mut group)| {
+            sort_pages(&mut group.pages, tax.sort);
+            TermListing {
+                url: term_page_url(cfg, tax, &slugs[&key], 0),
+                count: group.pages.len(),
+                pages: group.pages.into_iter().cloned().collect(),
+                name: group.name,
+            }
+        })
+        .collect();
+    sort_index(&mut terms, tax.index_sort);
+    TaxonomyListing {
+        name: tax.name.clone(),
+        url: format!("/{}/", tax.path()),
+        terms,
+    }
+}
+
+pub fn summarize(
+    rel: &str,
     out_root: &Path,
     out_path: &Path,
-    tags: &[String],
-    title: &str,
+    meta: &PageMeta,
+    taxonomies: &[TaxonomyConfig],
 ) -> PageSummary {
     PageSummary {
-        title: title.to_string(),
+        title: meta.title.as_deref().unwrap_or("Untitled").to_string(),
         url: url_for_out_path(out_root, out_path),
-        tags: tags.to_vec(),
+        path: rel.replace('\\', "/"),
+        date: meta.date.clone(),
+        weight: meta.weight,
+        tags: meta.tags.clone(),
+        taxonomies: taxonomies
+            .iter()
+            .filter(|t| t.name != "tags")
+            .map(|t| (t.name.clone(), meta.terms(&t.name)))
+            .collect(),
+    }
+}
+
+/// Files `write_taxonomy_pages` produces for `pages`, each with the term it
+/// belongs to (`None` for the index).
+pub fn taxonomy_output_paths(
+    cfg: &SiteConfig,
+    tax: &TaxonomyConfig,
+    out_root: &Path,
+    pages: &[PageSummary],
+) -> Vec<(Option<String>, PathBuf)> {
+    let groups = group_by_term(pages, tax);
+    if groups.is_empty() {
+        return Vec::new();
     }
+    let slugs = term_slugs(cfg, &groups);
+    let mut paths = vec![(None, taxonomy_dir(out_root, tax).join("index.html"))];
+    for (term, group) in groups {
+        let slug = &slugs[&term];
+        let items = &group.pages;
+        for i in 0..paginate(items, page_size(cfg, tax, items.len())).len() {
+            paths.push((
+                Some(term.clone()),
+                term_page_path(cfg, tax, out_root, slug, i),
+            ));
+        }
+        let redirect = first_page_redirect(cfg, tax, out_root, slug);
+        paths.push((Some(term.clone()), redirect.out_path));
+        if tax.feed {
+            paths.push((Some(term.clone()), term_feed_path(out_root, tax, slug)));
+        }
+    }
+    paths
+}
+
+fn taxonomy_dir(out_root: &Path, tax: &TaxonomyConfig) -> PathBuf {
+    let mut dir = out_root.to_path_buf();
+    dir.extend(tax.path().split('/').filter(|s| !s.is_empty()));
+    dir
+}
+
+fn term_page_path(
+    cfg: &SiteConfig,
+    tax: &TaxonomyConfig,
+    out_root: &Path,
+    slug: &str,
+    idx: usize,
+) -> PathBuf {
+    out_path_for_url(out_root, &term_page_url(cfg, tax, slug, idx), false)
 }
 
-fn tag_page_url(slug: &str, idx: usize) -> String {
+/// An RFC 3339 timestamp for Atom: bare dates are taken as midnight UTC.
+fn atom_date(date: &str) -> String {
+    if date.contains('T') {
+        date.to_string()
+    } else {
+        format!("{date}T00:00:00Z")
+    }
+}
+
+fn term_feed_path(out_root: &Path, tax: &TaxonomyConfig, slug: &str) -> PathBuf {
+    taxonomy_dir(out_root, tax).join(slug).join("feed.xml")
+}
+
+fn term_page_url(cfg: &SiteConfig, tax: &TaxonomyConfig, slug: &str, idx: usize) -> String {
     if idx == 0 {
-        format!("/tags/{}/", slug)
+        format!("/{}/{}/", tax.path(), slug)
     } else {
-        format!("/tags/{}/page/{}/", slug, idx + 1)
+        let segment = tax.paginate_path(&cfg.pagination);
+        format!("/{}/{}/{}/{}/", tax.path(), slug, segment, idx + 1)
     }
 }
 
+/// `/<term>/page/1/` is never linked but easy to guess, so it points back
+/// at the term page itself.
+fn first_page_redirect(
+    cfg: &SiteConfig,
+    tax: &TaxonomyConfig,
+    out_root: &Path,
+    slug: &str,
+) -> Redirect {
+    let segment = tax.paginate_path(&cfg.pagination);
+    let from = format!("/{}/{}/{}/1/", tax.path(), slug, segment);
+    Redirect::new(
+        out_root,
+        &from,
+        &term_page_path(cfg, tax, out_root, slug, 0),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,22 +477,187 @@ mod tests {
                 title: "A".into(),
                 url: "/a/".into(),
                 tags: vec!["rust".into(), "ssg".into()],
+                ..Default::default()
             },
             PageSummary {
                 title: "B".into(),
                 url: "/b/".into(),
                 tags: vec!["rust".into()],
+                ..Default::default()
             },
             PageSummary {
                 title: "C".into(),
                 url: "/c/".into(),
                 tags: vec!["cli".into()],
+                ..Default::default()
             },
         ];
 
         let g = group_by_tag(&pages);
-        assert_eq!(g.get("rust").unwrap().len(), 2);
-        assert_eq!(g.get("ssg").unwrap().len(), 1);
-        assert_eq!(g.get("cli").unwrap().len(), 1);
+        assert_eq!(g.get("rust").unwrap().pages.len(), 2);
+        assert_eq!(g.get("ssg").unwrap().pages.len(), 1);
+        assert_eq!(g.get("cli").unwrap().pages.len(), 1);
+    }
+
+    #[test]
+    fn groups_pages_by_custom_taxonomy() {
+        let page = |title: &str, authors: &[&str]| PageSummary {
+            title: title.into(),
+            taxonomies: [(
+                "authors".to_string(),
+                authors.iter().map(|a| a.to_string()).collect(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let pages = vec![page("A", &["Ada", "Linus"]), page("B", &["ada"])];
+
+        let authors = TaxonomyConfig {
+            name: "authors".into(),
+            ..Default::default()
+        };
+        let g = group_by_term(&pages, &authors);
+        assert_eq!(g["ada"].pages.len(), 2);
+        assert_eq!(g["linus"].pages.len(), 1);
+        assert!(group_by_tag(&pages).is_empty());
+    }
+
+    #[test]
+    fn keeps_original_spelling_and_merges_synonyms() {
+        let page = |tags: &[&str]| PageSummary {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        let pages = vec![
+            page(&["webassembly", "Rust"]),
+            page(&["WebAssembly", "rust"]),
+            page(&["WebAssembly", "js"]),
+            page(&["javascript"]),
+        ];
+        let tax = TaxonomyConfig {
+            synonyms: [("js".to_string(), "JavaScript".to_string())].into(),
+            ..TaxonomyConfig::tags()
+        };
+
+        let g = group_by_term(&pages, &tax);
+        assert_eq!(g["webassembly"].name, "WebAssembly");
+        assert_eq!(g["rust"].name, "Rust");
+        assert_eq!(g["javascript"].name, "JavaScript");
+        assert_eq!(g["javascript"].pages.len(), 2);
+        assert!(!g.contains_key("js"));
+    }
+
+    #[test]
+    fn terms_sharing_a_slug_get_their_own_pages() {
+        let pages = vec![PageSummary {
+            tags: vec!["C".into(), "C++".into(), "C#".into(), "c-1".into()],
+            ..Default::default()
+        }];
+        let cfg = SiteConfig::default();
+        let listing = taxonomy_listing(&cfg, &TaxonomyConfig::tags(), &pages);
+        let mut urls: Vec<(&str, &str)> = listing
+            .terms
+            .iter()
+            .map(|t| (t.name.as_str(), t.url.as_str()))
+            .collect();
+        urls.sort();
+        assert_eq!(
+            urls,
+            [
+                ("C", "/tags/c/"),
+                ("C#", "/tags/c-2/"),
+                ("C++", "/tags/c-3/"),
+                ("c-1", "/tags/c-1/"),
+            ]
+        );
+    }
+
+    fn summary(title: &str, date: Option<&str>, weight: Option<i64>) -> PageSummary {
+        PageSummary {
+            title: title.into(),
+            url: format!("/{}/", title.to_lowercase()),
+            date: date.map(String::from),
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn titles(pages: &[&PageSummary]) -> Vec<String> {
+        pages.iter().map(|p| p.title.clone()).collect()
+    }
+
+    #[test]
+    fn sorts_term_pages_deterministically() {
+        let pages = [
+            summary("b", Some("2024-01-01"), None),
+            summary("Undated", None, Some(1)),
+            summary("a", Some("2024-01-01"), Some(2)),
+            summary("Newest", Some("2025-06-30"), None),
+        ];
+        let mut refs: Vec<&PageSummary> = pages.iter().collect();
+
+        sort_pages(&mut refs, SortBy::Date);
+        assert_eq!(titles(&refs), ["Newest", "a", "b", "Undated"]);
+
+        sort_pages(&mut refs, SortBy::Title);
+        assert_eq!(titles(&refs), ["a", "b", "Newest", "Undated"]);
+
+        sort_pages(&mut refs, SortBy::Weight);
+        assert_eq!(titles(&refs), ["Undated", "a", "Newest", "b"]);
+    }
+
+    #[test]
+    fn sorts_index_by_name_or_count() {
+        let term = |name: &str, count| TermListing {
+            name: name.into(),
+            count,
+            url: String::new(),
+            pages: Vec::new(),
+        };
+        let mut terms = vec![term("web", 1), term("rust", 3), term("cli", 1)];
+
+        sort_index(&mut terms, IndexSort::Name);
+        let names: Vec<&str> = terms.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["cli", "rust", "web"]);
+
+        sort_index(&mut terms, IndexSort::Count);
+        let names: Vec<&str> = terms.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["rust", "cli", "web"]);
+    }
+
+    #[test]
+    fn output_paths_follow_taxonomy_prefix() {
+        let tax = TaxonomyConfig {
+            name: "authors".into(),
+            path: Some("people".into()),
+            paginate: Some(1),
+            paginate_path: Some("p".into()),
+            feed: true,
+            ..Default::default()
+        };
+        let pages = vec![
+            PageSummary {
+                taxonomies: [("authors".to_string(), vec!["Ada".to_string()])].into(),
+                ..Default::default()
+            };
+            2
+        ];
+
+        let mut paths: Vec<PathBuf> =
+            taxonomy_output_paths(&SiteConfig::default(), &tax, Path::new("/o"), &pages)
+                .into_iter()
+                .map(|(_, p)| p)
+                .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/o/people/ada/feed.xml"),
+                PathBuf::from("/o/people/ada/index.html"),
+                PathBuf::from("/o/people/ada/p/1/index.html"),
+                PathBuf::from("/o/people/ada/p/2/index.html"),
+                PathBuf::from("/o/people/index.html"),
+            ]
+        );
     }
 }
@@ -1,34 +1,99 @@
 use crate::{
     config::SiteConfig,
-    content::{Document, PageMeta},
-    render::render_html_sanitized,
+    content::{Document, PageMeta, is_section_index},
+    context::SiteContext,
+    functions,
+    render::render_page_html,
+    sections::SectionListing,
+    taxonomy::PageSummary,
 };
 use serde::Serialize;
-use std::{io, path::Path};
-use tera::{Context, Tera};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+use tera::Tera;
+use walkdir::WalkDir;
+
+/// Fallback templates compiled into the binary, used when a site does not
+/// define a template of the same name.
+const BUILTIN: &[(&str, &str)] = &[
+    ("tag.html", include_str!("builtin/tag.html")),
+    ("tags.html", include_str!("builtin/tags.html")),
+    ("section.html", include_str!("builtin/section.html")),
+    ("404.html", include_str!("builtin/404.html")),
+    ("feed.xml", include_str!("builtin/feed.xml")),
+];
+
+const BUILTIN_PREFIX: &str = "__builtin/";
 
 pub struct Templates {
     tera: Tera,
+    dirs: Vec<PathBuf>,
 }
 
 impl Templates {
     pub fn load_from(dir: &Path) -> io::Result<Self> {
-        let pattern = format!("{}/**/*", dir.display());
-        let tera = Tera::new(&pattern).map_err(map_tera_err)?;
-        Ok(Self { tera })
+        Self::load_layers(&[dir.to_path_buf()])
     }
 
-    pub fn render_page(&self, cfg: &SiteConfig, doc: &Document<PageMeta>) -> io::Result<String> {
-        let body_html = render_html_sanitized(doc);
+    /// Loads every template under `dirs`, named by their path relative to
+    /// the directory. A template in a later directory replaces one of the
+    /// same name in an earlier one, so a theme's directory goes first and
+    /// the site's last.
+    pub fn load_layers(dirs: &[PathBuf]) -> io::Result<Self> {
+        let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for dir in dirs {
+            for e in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+                if !e.file_type().is_file() {
+                    continue;
+                }
+                let name = e
+                    .path()
+                    .strip_prefix(dir)
+                    .unwrap_or(e.path())
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                files.insert(name, e.into_path());
+            }
+        }
 
-        #[derive(Serialize)]
-        struct SiteView<'a> {
-            title: &'a str,
-            base_url: &'a str,
-            theme: &'a str,
-            description: &'a Option<String>,
-            author: &'a Option<String>,
+        let mut tera = Tera::default();
+        tera.add_template_files(files.iter().map(|(name, path)| (path, Some(name))))
+            .map_err(map_tera_err)?;
+        add_builtins(&mut tera)?;
+        Ok(Self {
+            tera,
+            dirs: dirs.to_vec(),
+        })
+    }
+
+    /// `name` if the site defines it, else the built-in `fallback` if there
+    /// is one. Unknown names are returned as-is so render errors mention them.
+    pub fn resolve(&self, name: &str, fallback: &str) -> String {
+        let defined = |n: &str| self.tera.get_template_names().any(|t| t == n);
+        let builtin = format!("{BUILTIN_PREFIX}{fallback}");
+        if !defined(name) && defined(&builtin) {
+            builtin
+        } else {
+            name.to_string()
         }
+    }
+
+    /// Renders a page. For a bundle, relative links in its body resolve
+    /// against `link_base` and `assets` are the URLs of the bundle's files.
+    /// Section pages also get the listing page they render as `section`.
+    pub fn render_page(
+        &self,
+        site: &SiteContext,
+        cfg: &SiteConfig,
+        doc: &Document<PageMeta>,
+        link_base: Option<&str>,
+        assets: &[String],
+        section: Option<&SectionListing>,
+    ) -> io::Result<String> {
+        let body_html = render_page_html(doc, cfg.slugify, link_base);
 
         #[derive(Serialize)]
         struct PageView<'a> {
@@ -38,6 +103,7 @@ impl Templates {
             date: &'a Option<String>,
             draft: bool,
             content: &'a str,
+            assets: &'a [String],
         }
 
         let meta = doc.front_matter.clone().unwrap_or_default();
@@ -50,14 +116,6 @@ impl Templates {
                 .to_string()
         });
 
-        let site = SiteView {
-            title: &cfg.title,
-            base_url: &cfg.base_url,
-            theme: &cfg.theme,
-            description: &cfg.description,
-            author: &cfg.author,
-        };
-
         let page = PageView {
             title,
             slug: &slug_owned,
@@ -65,36 +123,55 @@ impl Templates {
             date: &meta.date,
             draft: meta.draft,
             content: &body_html,
+            assets,
         };
 
-        let mut ctx = Context::new();
-        ctx.insert("site", &site);
-        ctx.insert("page", &page);
+        let mut vars = tera::Context::new();
+        vars.insert("page", &page);
+        if let Some(section) = section {
+            vars.insert("section", section);
+        }
+
+        let template = template_for(&doc.path, &meta);
+        site.render(&self.tera, &self.resolve(template, template), vars)
+            .map_err(map_tera_err)
+    }
 
-        let tpl = meta.template.as_deref().unwrap_or("post.html");
+    /// Makes the site-aware functions and filters of [`crate::functions`]
+    /// available to every template.
+    pub fn register_site(&mut self, cfg: &SiteConfig, site: &SiteContext, pages: &[PageSummary]) {
+        functions::register(&mut self.tera, cfg, site, pages);
+    }
 
-        self.tera.render(tpl, &ctx).map_err(map_tera_err)
+    /// Renders `404.html`, falling back to the built-in page.
+    pub fn render_not_found(&self, site: &SiteContext) -> io::Result<String> {
+        site.render(
+            &self.tera,
+            &self.resolve("404.html", "404.html"),
+            tera::Context::new(),
+        )
+        .map_err(map_tera_err)
     }
 
+    /// Reads every template from disk again. Site functions have to be
+    /// registered again afterwards.
     #[allow(dead_code)]
     pub fn full_reload(&mut self) -> io::Result<()> {
-        self.tera.full_reload().map_err(map_tera_err)
+        *self = Self::load_layers(&self.dirs)?;
+        Ok(())
     }
 
-    pub fn render_with<T: serde::Serialize>(
+    pub fn render_with<T: Serialize>(
         &self,
         template: &str,
-        site: &crate::config::SiteConfig,
+        site: &SiteContext,
         key: &str,
         data: &T,
-    ) -> std::io::Result<String> {
-        use tera::Context;
-        let mut ctx = Context::new();
-        ctx.insert("site", site);
-        ctx.insert(key, data);
-        self.tera
-            .render(template, &ctx)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    ) -> io::Result<String> {
+        let mut vars = tera::Context::new();
+        vars.insert(key, data);
+        site.render(&self.tera, template, vars)
+            .map_err(map_tera_err)
     }
 
     pub fn tera(&self) -> &tera::Tera {
@@ -102,6 +179,25 @@ impl Templates {
     }
 }
 
+/// The template a page renders with: its `template` front matter, else
+/// `section.html` for section pages and `post.html` for the rest.
+pub fn template_for<'a>(path: &Path, meta: &'a PageMeta) -> &'a str {
+    match &meta.template {
+        Some(template) => template,
+        None if is_section_index(path) => "section.html",
+        None => "post.html",
+    }
+}
+
+fn add_builtins(tera: &mut Tera) -> io::Result<()> {
+    tera.add_raw_templates(
+        BUILTIN
+            .iter()
+            .map(|(name, text)| (format!("{BUILTIN_PREFIX}{name}"), *text)),
+    )
+    .map_err(map_tera_err)
+}
+
 fn map_tera_err(err: tera::Error) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, err)
 }
@@ -109,6 +205,7 @@ fn map_tera_err(err: tera::Error) -> io::Error {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::context::Environment;
     use std::fs;
     use tempfile::tempdir;
 
@@ -145,10 +242,71 @@ mod tests {
         };
 
         let t = Templates::load_from(&tpldir)?;
-        let html = t.render_page(&cfg, &doc)?;
+        let site = SiteContext::new(
+            &cfg,
+            Environment::Production,
+            &[],
+            Default::default(),
+            &Default::default(),
+        );
+        let html = t.render_page(&site, &cfg, &doc, None, &[], None)?;
 
         assert!(html.contains("<title>Hello — My Blog</title>"));
         assert!(html.contains("<h1>Hello</h1>"));
         Ok(())
     }
+
+    #[test]
+    fn site_templates_override_builtins() -> io::Result<()> {
+        let tmp = tempdir()?;
+        let tpldir = tmp.path().join("templates");
+        fs::create_dir_all(&tpldir)?;
+        fs::write(tpldir.join("tags.html"), "mine")?;
+
+        let t = Templates::load_from(&tpldir)?;
+        assert_eq!(t.resolve("tags.html", "tags.html"), "tags.html");
+        assert_eq!(t.resolve("tag.html", "tag.html"), "__builtin/tag.html");
+        assert_eq!(
+            t.resolve("authors/term.html", "tag.html"),
+            "__builtin/tag.html"
+        );
+        assert_eq!(t.resolve("post.html", "post.html"), "post.html");
+
+        let site = SiteContext::new(
+            &SiteConfig::default(),
+            Environment::Production,
+            &[],
+            Default::default(),
+            &Default::default(),
+        );
+        let html = t.render_not_found(&site)?;
+        assert!(html.contains("Page not found"));
+        Ok(())
+    }
+
+    #[test]
+    fn site_templates_override_theme_templates() -> io::Result<()> {
+        let tmp = tempdir()?;
+        let theme = tmp.path().join("theme");
+        let site = tmp.path().join("site");
+        fs::create_dir_all(theme.join("partials"))?;
+        fs::create_dir_all(&site)?;
+        fs::write(
+            theme.join("base.html"),
+            r#"[{% include "partials/nav.html" %}|{% block body %}theme{% endblock body %}]"#,
+        )?;
+        fs::write(theme.join("partials/nav.html"), "theme-nav")?;
+        fs::write(theme.join("post.html"), "theme-post")?;
+        fs::create_dir_all(site.join("partials"))?;
+        fs::write(site.join("partials/nav.html"), "site-nav")?;
+        fs::write(
+            site.join("post.html"),
+            r#"{% extends "base.html" %}{% block body %}site{% endblock body %}"#,
+        )?;
+
+        let t = Templates::load_layers(&[theme, site])?;
+        let html = t.tera().render("post.html", &tera::Context::new()).unwrap();
+        assert_eq!(html, "[site-nav|site]");
+        Ok(())
+    }
 }
@@ -1,5 +1,10 @@
 use clap::Parser;
-use std::{fs, path::PathBuf, thread, time::Duration};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
 #[test]
 fn cli_parses_build_args() {
@@ -13,7 +18,7 @@ fn cli_parses_build_args() {
     }
 }
 
-fn write_min_site(root: &std::path::Path) {
+fn write_min_site(root: &Path) {
     fs::create_dir_all(root.join("templates")).unwrap();
     fs::write(
         root.join("templates/base.html"),
@@ -42,6 +47,16 @@ fn write_min_site(root: &std::path::Path) {
     .unwrap();
 }
 
+fn build_site(src: &Path, out: &Path) {
+    ssg::run(ssg::cli::Args {
+        action: ssg::cli::Action::Build {
+            src: src.to_path_buf(),
+            out: out.to_path_buf(),
+        },
+    })
+    .unwrap();
+}
+
 #[test]
 fn build_then_skip_when_unchanged() {
     let tmp = tempfile::tempdir().unwrap();
@@ -57,7 +72,7 @@ fn build_then_skip_when_unchanged() {
             out: out.clone(),
         },
     };
-    ssg::run(args);
+    ssg::run(args).unwrap();
 
     let page = out.join("posts/first/index.html");
     let m1 = fs::metadata(&page).unwrap().modified().unwrap();
@@ -68,7 +83,7 @@ fn build_then_skip_when_unchanged() {
             out: out.clone(),
         },
     };
-    ssg::run(args2);
+    ssg::run(args2).unwrap();
     let m2 = fs::metadata(&page).unwrap().modified().unwrap();
 
     assert_eq!(m1, m2, "content page should be skipped");
@@ -89,7 +104,8 @@ fn template_change_triggers_rebuild() {
             src: src.clone(),
             out: out.clone(),
         },
-    });
+    })
+    .unwrap();
     let page = out.join("posts/first/index.html");
     let m1 = fs::metadata(&page).unwrap().modified().unwrap();
 
@@ -105,8 +121,954 @@ fn template_change_triggers_rebuild() {
             src: src.clone(),
             out: out.clone(),
         },
-    });
+    })
+    .unwrap();
     let m2 = fs::metadata(&page).unwrap().modified().unwrap();
 
     assert!(m2 > m1, "page should be rebuilt after template change");
 }
+
+#[test]
+fn unrelated_template_change_skips_pages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&out).unwrap();
+    write_min_site(&src);
+    fs::write(src.join("templates/other.html"), "other").unwrap();
+
+    build_site(&src, &out);
+    let page = out.join("posts/first/index.html");
+    let m1 = fs::metadata(&page).unwrap().modified().unwrap();
+
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(src.join("templates/other.html"), "other CHANGED").unwrap();
+
+    build_site(&src, &out);
+    let m2 = fs::metadata(&page).unwrap().modified().unwrap();
+
+    assert_eq!(m1, m2, "page should not depend on other.html");
+}
+
+#[test]
+fn config_change_triggers_rebuild() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&out).unwrap();
+    write_min_site(&src);
+
+    build_site(&src, &out);
+    let page = out.join("posts/first/index.html");
+    let m1 = fs::metadata(&page).unwrap().modified().unwrap();
+
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(
+        src.join("site.toml"),
+        "title = \"Renamed\"\nbase_url = \"https://example.com/\"\n",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    let m2 = fs::metadata(&page).unwrap().modified().unwrap();
+
+    assert!(m2 > m1, "page should be rebuilt after site.toml change");
+}
+
+#[test]
+fn missing_output_is_rebuilt() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&out).unwrap();
+    write_min_site(&src);
+
+    build_site(&src, &out);
+    let page = out.join("posts/first/index.html");
+    fs::remove_file(&page).unwrap();
+
+    build_site(&src, &out);
+    assert!(page.exists(), "deleted output should be written again");
+}
+
+#[test]
+fn cached_pages_still_listed_on_tag_pages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [rust]\n---\n# First",
+    )
+    .unwrap();
+    fs::write(
+        src.join("templates/tag.html"),
+        "{% for p in tag.pages %}<a href=\"{{ p.url }}\">{{ p.title }}</a>{% endfor %}",
+    )
+    .unwrap();
+    fs::write(src.join("templates/tags.html"), "tags").unwrap();
+
+    build_site(&src, &out);
+    fs::remove_file(out.join("tags/rust/index.html")).unwrap();
+    build_site(&src, &out);
+
+    let tag_page = fs::read_to_string(out.join("tags/rust/index.html")).unwrap();
+    assert!(tag_page.contains(">First</a>"));
+}
+
+#[test]
+fn colliding_output_paths_fail_the_build() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(src.join("posts/Hello World.md"), "# one").unwrap();
+    fs::write(src.join("posts/hello-world.md"), "# two").unwrap();
+
+    let result = ssg::run(ssg::cli::Args {
+        action: ssg::cli::Action::Build {
+            src: src.clone(),
+            out: out.clone(),
+        },
+    });
+    assert!(result.is_err());
+
+    assert!(!out.join("posts/hello-world/index.html").exists());
+    assert!(!out.join("posts/first/index.html").exists());
+}
+
+#[test]
+fn page_render_failures_fail_the_build() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(src.join("templates/broken.html"), "{{ missing.field }}").unwrap();
+    fs::write(
+        src.join("posts/broken.md"),
+        "---\ntitle: Broken\ntemplate: broken.html\n---\n",
+    )
+    .unwrap();
+
+    let result = ssg::run(ssg::cli::Args {
+        action: ssg::cli::Action::Build {
+            src: src.clone(),
+            out: out.clone(),
+        },
+    });
+    assert_eq!(result.unwrap_err().to_string(), "build failed (1 failed)");
+    // The other pages are still built.
+    assert!(out.join("posts/first/index.html").exists());
+}
+
+#[test]
+fn aliases_write_redirect_pages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\naliases: [/old-first/]\n---\n# First",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+
+    let html = fs::read_to_string(out.join("old-first/index.html")).unwrap();
+    assert!(html.contains("url=http://localhost/posts/first/"));
+}
+
+#[test]
+fn static_redirects_file_collides_with_generated_rules() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    let site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    fs::write(
+        src.join("site.toml"),
+        format!("{site_toml}\n[redirects]\nnetlify = true\n"),
+    )
+    .unwrap();
+    fs::write(src.join("_redirects"), "/from /to 302\n").unwrap();
+
+    let result = ssg::run(ssg::cli::Args {
+        action: ssg::cli::Action::Build {
+            src: src.clone(),
+            out: out.clone(),
+        },
+    });
+    assert!(result.is_err());
+    assert!(!out.join("_redirects").exists());
+}
+
+#[test]
+fn custom_taxonomy_writes_term_pages_and_index() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("site.toml"),
+        r#"
+            title = "T"
+            [[taxonomies]]
+            name = "authors"
+            path = "people"
+        "#,
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\nauthors: [Ada]\n---\n# First",
+    )
+    .unwrap();
+    fs::create_dir_all(src.join("templates/authors")).unwrap();
+    fs::write(
+        src.join("templates/authors/term.html"),
+        "{{ taxonomy.name }}:{{ term.name }}:{% for p in term.pages %}{{ p.title }}{% endfor %}",
+    )
+    .unwrap();
+    fs::write(
+        src.join("templates/authors/index.html"),
+        "{% for t in terms %}{{ t.name }}={{ t.count }}{% endfor %}",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+
+    let term = fs::read_to_string(out.join("people/ada/index.html")).unwrap();
+    assert_eq!(term, "authors:Ada:First");
+    let index = fs::read_to_string(out.join("people/index.html")).unwrap();
+    assert_eq!(index, "Ada=1");
+    assert!(!out.join("tags").exists());
+}
+
+#[test]
+fn builtin_feed_has_atom_timestamps() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("site.toml"),
+        "title = \"T\"\n[[taxonomies]]\nname = \"tags\"\nfeed = true\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ndate: 2024-01-02\ntags: [rust]\n---\n# First",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/second.md"),
+        "---\ntitle: Second\ndate: 2024-03-04T10:30:00Z\ntags: [rust]\n---\n# Second",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+
+    let feed = fs::read_to_string(out.join("tags/rust/feed.xml")).unwrap();
+    let updated: Vec<&str> = feed
+        .match_indices("<updated>")
+        .map(|(i, _)| &feed[i + 9..i + 9 + feed[i + 9..].find('<').unwrap()])
+        .collect();
+    assert_eq!(
+        updated,
+        [
+            "2024-03-04T10:30:00Z",
+            "2024-03-04T10:30:00Z",
+            "2024-01-02T00:00:00Z"
+        ]
+    );
+}
+
+#[test]
+fn stale_term_pages_are_removed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("site.toml"),
+        "title = \"T\"\n[[taxonomies]]\nname = \"tags\"\npaginate = 1\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [rust, web]\n---\n# First",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/second.md"),
+        "---\ntitle: Second\ntags: [rust]\n---\n# Second",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    assert!(out.join("tags/web/index.html").is_file());
+    assert!(out.join("tags/rust/page/2/index.html").is_file());
+
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [rust]\n---\n# First",
+    )
+    .unwrap();
+    fs::remove_file(src.join("posts/second.md")).unwrap();
+    build_site(&src, &out);
+
+    assert!(!out.join("tags/web").exists());
+    assert!(!out.join("tags/rust/page/2").exists());
+    assert!(out.join("tags/rust/index.html").is_file());
+    assert!(out.join("tags/rust/page/1/index.html").is_file());
+}
+
+#[test]
+fn section_pages_list_their_pages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("posts/_index.md"),
+        "---\ntitle: Blog\n---\nAll posts",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    let html = fs::read_to_string(out.join("posts/index.html")).unwrap();
+    assert!(
+        html.contains("<h1>Blog</h1>"),
+        "built-in section.html: {html}"
+    );
+    assert!(html.contains(r#"<a href="/posts/first/">First</a>"#));
+
+    fs::write(
+        src.join("templates/section.html"),
+        "{{ page.title }}:{% for p in section.pages %}{{ p.title }}{% endfor %}",
+    )
+    .unwrap();
+    fs::write(src.join("posts/second.md"), "---\ntitle: Second\n---\n").unwrap();
+    build_site(&src, &out);
+    let html = fs::read_to_string(out.join("posts/index.html")).unwrap();
+    assert_eq!(html, "Blog:FirstSecond");
+}
+
+#[test]
+fn section_pages_paginate_with_their_own_settings() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("site.toml"),
+        "title = \"T\"\n[pagination]\nper_page = 10\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/_index.md"),
+        "---\ntitle: Blog\npaginate: 1\npaginate_path: p\n---\n",
+    )
+    .unwrap();
+    fs::write(src.join("posts/second.md"), "---\ntitle: Second\n---\n").unwrap();
+    fs::write(
+        src.join("templates/section.html"),
+        "{{ section.pagination.current }}/{{ section.pagination.total }}:\
+         {% for p in section.pages %}{{ p.title }}{% endfor %}",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    let page = |path: &str| fs::read_to_string(out.join(path)).unwrap();
+    assert_eq!(page("posts/index.html"), "1/2:First");
+    assert_eq!(page("posts/p/2/index.html"), "2/2:Second");
+    assert!(page("posts/p/1/index.html").contains("/posts/\" />"));
+
+    fs::remove_file(src.join("posts/second.md")).unwrap();
+    build_site(&src, &out);
+    assert_eq!(page("posts/index.html"), "1/1:First");
+    assert!(!out.join("posts/p/2").exists());
+}
+
+#[test]
+fn builtin_templates_cover_missing_site_templates() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+
+    build_site(&src, &out);
+    assert!(!out.join("tags").exists(), "no tags, no tag index");
+    let not_found = fs::read_to_string(out.join("404.html")).unwrap();
+    assert!(not_found.contains("Page not found"));
+
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [Rust]\n---\n# First",
+    )
+    .unwrap();
+    build_site(&src, &out);
+    let term = fs::read_to_string(out.join("tags/rust/index.html")).unwrap();
+    assert!(term.contains("<h1>Rust</h1>"));
+    assert!(term.contains(">First</a>"));
+    let index = fs::read_to_string(out.join("tags/index.html")).unwrap();
+    assert!(index.contains(">Rust</a> (1)"));
+}
+
+#[test]
+fn tags_sharing_a_slug_still_build() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [C, \"C++\", \"C#\"]\n---\n# First",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    for (slug, name) in [("c", "C"), ("c-1", "C#"), ("c-2", "C++")] {
+        let term = fs::read_to_string(out.join("tags").join(slug).join("index.html")).unwrap();
+        assert!(term.contains(&format!("<h1>{name}</h1>")), "{slug}: {term}");
+    }
+}
+
+#[test]
+fn theme_is_a_base_layer_the_site_overrides() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::remove_file(src.join("templates/base.html")).unwrap();
+    fs::write(
+        src.join("site.toml"),
+        "title = \"T\"\ntheme = \"plain\"\n[extra]\nfooter = \"site\"\n",
+    )
+    .unwrap();
+
+    let theme = src.join("themes/plain");
+    fs::create_dir_all(theme.join("templates")).unwrap();
+    fs::create_dir_all(theme.join("static/css")).unwrap();
+    fs::write(
+        theme.join("templates/base.html"),
+        "{% block content %}{% endblock %}|{{ site.extra.accent }}|{{ site.extra.footer }}",
+    )
+    .unwrap();
+    fs::write(theme.join("templates/post.html"), "theme post").unwrap();
+    fs::write(theme.join("static/css/theme.css"), "theme").unwrap();
+    fs::write(theme.join("static/robots.txt"), "theme").unwrap();
+    fs::write(theme.join("README.md"), "# Not content").unwrap();
+    fs::write(
+        theme.join("theme.toml"),
+        "[extra]\naccent = \"blue\"\nfooter = \"theme\"\n",
+    )
+    .unwrap();
+    fs::write(src.join("robots.txt"), "site").unwrap();
+
+    build_site(&src, &out);
+
+    let page = fs::read_to_string(out.join("posts/first/index.html")).unwrap();
+    assert!(page.ends_with("|blue|site"), "{page}");
+    assert_eq!(
+        fs::read_to_string(out.join("css/theme.css")).unwrap(),
+        "theme"
+    );
+    assert_eq!(fs::read_to_string(out.join("robots.txt")).unwrap(), "site");
+    assert!(!out.join("themes").exists());
+}
+
+#[test]
+fn data_files_reach_templates_and_rerender_on_change() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("templates/team.html"),
+        "{% for m in data.team.members %}{{ m.name }};{% endfor %}",
+    )
+    .unwrap();
+    fs::write(
+        src.join("team.md"),
+        "---\ntitle: Team\ntemplate: team.html\n---\n",
+    )
+    .unwrap();
+    fs::create_dir_all(src.join("data/team")).unwrap();
+    fs::write(src.join("data/team/members.csv"), "name,role\nAda,dev\n").unwrap();
+
+    build_site(&src, &out);
+    let team = out.join("team/index.html");
+    assert_eq!(fs::read_to_string(&team).unwrap(), "Ada;");
+    assert!(!out.join("data").exists());
+
+    let first = out.join("posts/first/index.html");
+    let first_mtime = fs::metadata(&first).unwrap().modified().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    fs::write(
+        src.join("data/team/members.csv"),
+        "name,role\nAda,dev\nLinus,ops\n",
+    )
+    .unwrap();
+    build_site(&src, &out);
+
+    assert_eq!(fs::read_to_string(&team).unwrap(), "Ada;Linus;");
+    let after = fs::metadata(&first).unwrap().modified().unwrap();
+    assert_eq!(first_mtime, after, "pages not reading data stay cached");
+}
+
+#[test]
+fn fingerprinted_assets_get_manifest_and_sri() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("css")).unwrap();
+    write_min_site(&src);
+    let mut site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    site_toml.push_str("\n[assets]\nfingerprint = true\n");
+    fs::write(src.join("site.toml"), site_toml).unwrap();
+    fs::write(
+        src.join("templates/page.html"),
+        r#"<link href="{{ asset_url(path="css/style.css") | safe }}" integrity="{{ asset_integrity(path="css/style.css") | safe }}">"#,
+    )
+    .unwrap();
+    fs::write(
+        src.join("about.md"),
+        "---\ntitle: About\ntemplate: page.html\n---\n",
+    )
+    .unwrap();
+    fs::write(src.join("css/style.css"), "body{color:red}").unwrap();
+    fs::write(src.join("logo.svg"), "<svg/>").unwrap();
+
+    build_site(&src, &out);
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out.join("asset-manifest.json")).unwrap())
+            .unwrap();
+    let url = manifest["css/style.css"]["url"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_ne!(url, "/css/style.css");
+    assert!(out.join(url.trim_start_matches('/')).exists());
+    assert!(!out.join("css/style.css").exists());
+    assert_eq!(manifest["logo.svg"]["url"], "/logo.svg");
+
+    let about = out.join("about/index.html");
+    let html = fs::read_to_string(&about).unwrap();
+    assert!(html.contains(&format!(r#"href="{url}""#)));
+    assert!(html.contains(r#"integrity="sha384-"#));
+
+    fs::write(src.join("css/style.css"), "body{color:blue}").unwrap();
+    build_site(&src, &out);
+    let html = fs::read_to_string(&about).unwrap();
+    assert!(!html.contains(&url), "pages pick up the new fingerprint");
+}
+
+#[test]
+fn scss_is_compiled_and_assets_minified_with_source_maps() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("css")).unwrap();
+    write_min_site(&src);
+    let mut site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    site_toml.push_str(
+        r#"
+[assets]
+minify = true
+
+[[assets.bundles]]
+output = "js/all.js"
+inputs = ["js/a.js", "js/b.js"]
+"#,
+    );
+    fs::write(src.join("site.toml"), site_toml).unwrap();
+    fs::write(src.join("css/_colors.scss"), "$accent: #c00;").unwrap();
+    fs::write(
+        src.join("css/main.scss"),
+        "@use 'colors';\nnav {\n  a { color: colors.$accent; }\n}\n",
+    )
+    .unwrap();
+    fs::create_dir_all(src.join("js")).unwrap();
+    fs::write(src.join("js/a.js"), "// a\nconst a = 1;\n").unwrap();
+    fs::write(src.join("js/b.js"), "const b = a + 1;\n").unwrap();
+
+    build_site(&src, &out);
+
+    assert_eq!(
+        fs::read_to_string(out.join("css/main.css")).unwrap(),
+        "nav a{color:#c00}\n/*# sourceMappingURL=main.css.map */"
+    );
+    let map = fs::read_to_string(out.join("css/main.css.map")).unwrap();
+    let map: serde_json::Value = serde_json::from_str(&map).unwrap();
+    assert_eq!(map["sources"], serde_json::json!(["/css/main.scss.css"]));
+    assert_eq!(
+        map["sourcesContent"],
+        serde_json::json!(["nav a {\n  color: #c00;\n}\n"])
+    );
+    assert!(!out.join("css/main.scss").exists());
+    assert!(!out.join("css/_colors.scss").exists());
+    assert!(!src.join(".ssg-cache").exists());
+    assert_eq!(
+        fs::read_to_string(out.join("js/all.js")).unwrap(),
+        "const a=1;\nconst b=a+1;\n//# sourceMappingURL=all.js.map"
+    );
+    assert_eq!(
+        fs::read_to_string(out.join("js/a.js")).unwrap(),
+        "const a=1;\n//# sourceMappingURL=a.js.map"
+    );
+}
+
+#[test]
+fn minify_html_applies_to_every_generated_page() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    let site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    fs::write(
+        src.join("site.toml"),
+        format!("minify_html = true\n{site_toml}"),
+    )
+    .unwrap();
+    fs::write(
+        src.join("templates/base.html"),
+        "<!doctype html>\n<html>\n  <!-- layout -->\n  <body>\n    {% block content %}{% endblock %}\n  </body>\n</html>\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\ntags: [rust]\n---\nSome   *text*\n\n```\nfn  main() {\n}\n```\n",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+
+    let page = fs::read_to_string(out.join("posts/first/index.html")).unwrap();
+    assert!(page.starts_with("<!doctype html><html><body><h1>First</h1>"));
+    assert!(!page.contains("layout"));
+    assert!(page.contains("<code>fn  main() {\n}\n</code></pre>"));
+    let term = fs::read_to_string(out.join("tags/rust/index.html")).unwrap();
+    assert!(!term.contains("\n  "), "{term}");
+}
+
+#[test]
+fn templates_resize_images_and_get_their_dimensions() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("img")).unwrap();
+    write_min_site(&src);
+    let mut site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    site_toml.push_str("\n[images]\nwidths = [8, 16]\n");
+    fs::write(src.join("site.toml"), site_toml).unwrap();
+    image::RgbImage::from_pixel(32, 24, image::Rgb([0, 120, 200]))
+        .save(src.join("img/hero.jpg"))
+        .unwrap();
+    fs::write(
+        src.join("templates/gallery.html"),
+        r#"{% set m = get_image_metadata(path="img/hero.jpg") %}{% set s = image_srcset(path="img/hero.jpg", format="webp") %}{% set t = resize_image(path="img/hero.jpg", height=6) %}<img src="{{ s.src | safe }}" srcset="{{ s.srcset | safe }}" width="{{ m.width }}" height="{{ m.height }}"><img src="{{ t.url | safe }}" width="{{ t.width }}" height="{{ t.height }}">"#,
+    )
+    .unwrap();
+    fs::write(
+        src.join("gallery.md"),
+        "---\ntitle: Gallery\ntemplate: gallery.html\n---\n",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+
+    let html = fs::read_to_string(out.join("gallery/index.html")).unwrap();
+    assert!(html.contains(r#"width="32" height="24""#), "{html}");
+    assert!(html.contains(r#"width="8" height="6""#), "{html}");
+    let processed: Vec<_> = fs::read_dir(out.join("processed_images"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(processed.iter().filter(|n| n.ends_with(".webp")).count(), 3);
+    assert_eq!(processed.iter().filter(|n| n.ends_with(".jpg")).count(), 1);
+    assert!(html.contains(" 16w, /processed_images/hero."));
+    // The original is still copied as a static asset.
+    assert!(out.join("img/hero.jpg").exists());
+}
+
+#[test]
+fn unused_processed_images_are_removed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("img")).unwrap();
+    write_min_site(&src);
+    image::RgbImage::from_pixel(32, 24, image::Rgb([0, 120, 200]))
+        .save(src.join("img/hero.jpg"))
+        .unwrap();
+    let gallery = |width: u32| {
+        fs::write(
+            src.join("gallery.md"),
+            format!("---\ntitle: Gallery {width}\ntemplate: gallery.html\n---\n"),
+        )
+        .unwrap();
+        fs::write(
+            src.join("templates/gallery.html"),
+            format!(
+                r#"{{% set t = resize_image(path="img/hero.jpg", width={width}) %}}<img src="{{{{ t.url | safe }}}}">"#
+            ),
+        )
+        .unwrap();
+    };
+    let processed = || -> Vec<String> {
+        match fs::read_dir(out.join("processed_images")) {
+            Ok(dir) => dir
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    gallery(8);
+    build_site(&src, &out);
+    let first = processed();
+    assert_eq!(first.len(), 1);
+
+    // An unchanged page keeps its image across builds.
+    build_site(&src, &out);
+    assert_eq!(processed(), first);
+
+    gallery(16);
+    build_site(&src, &out);
+    let second = processed();
+    assert_eq!(second.len(), 1);
+    assert_ne!(second, first);
+    let html = fs::read_to_string(out.join("gallery/index.html")).unwrap();
+    assert!(
+        html.contains(&format!("/processed_images/{}", second[0])),
+        "{html}"
+    );
+
+    fs::remove_file(src.join("gallery.md")).unwrap();
+    build_site(&src, &out);
+    assert!(!out.join("processed_images").exists());
+
+    // The directory belongs to the image processor.
+    fs::create_dir_all(src.join("processed_images")).unwrap();
+    fs::write(src.join("processed_images/logo.txt"), "x").unwrap();
+    let result = ssg::run(ssg::cli::Args {
+        action: ssg::cli::Action::Build {
+            src: src.clone(),
+            out: out.clone(),
+        },
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn page_bundles_publish_their_files_next_to_the_page() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("posts/my-trip/img")).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("templates/trip.html"),
+        "{{ page.content | safe }}|{% for a in page.assets %}{{ a | safe }};{% endfor %}",
+    )
+    .unwrap();
+    fs::write(
+        src.join("posts/my-trip/index.md"),
+        "---\ntitle: Trip\npath: /journal/my-trip/\ntemplate: trip.html\n---\n![x](img/photo.jpg)\n",
+    )
+    .unwrap();
+    fs::write(src.join("posts/my-trip/img/photo.jpg"), "jpg").unwrap();
+    fs::write(src.join("posts/my-trip/notes.txt"), "txt").unwrap();
+    fs::write(
+        src.join("posts/first.md"),
+        "---\ntitle: First\n---\n![y](cover.png)\n",
+    )
+    .unwrap();
+    fs::write(src.join("posts/cover.png"), "png").unwrap();
+
+    build_site(&src, &out);
+
+    let trip = fs::read_to_string(out.join("journal/my-trip/index.html")).unwrap();
+    assert!(
+        trip.contains(r#"src="/journal/my-trip/img/photo.jpg""#),
+        "{trip}"
+    );
+    assert!(trip.ends_with("|/journal/my-trip/img/photo.jpg;/journal/my-trip/notes.txt;"));
+    assert!(out.join("journal/my-trip/img/photo.jpg").exists());
+    assert!(!out.join("posts/my-trip").exists());
+
+    // A page that is not a bundle keeps its links as written.
+    let first = fs::read_to_string(out.join("posts/first/index.html")).unwrap();
+    assert!(first.contains(r#"src="cover.png""#), "{first}");
+    assert!(out.join("posts/cover.png").exists());
+
+    // A new bundle file reaches `page.assets` on the next build.
+    fs::write(src.join("posts/my-trip/map.svg"), "<svg/>").unwrap();
+    build_site(&src, &out);
+    let trip = fs::read_to_string(out.join("journal/my-trip/index.html")).unwrap();
+    assert!(trip.contains("/journal/my-trip/map.svg;"), "{trip}");
+}
+
+#[test]
+fn ignored_and_hidden_files_are_not_built_or_copied() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    let site_toml = fs::read_to_string(src.join("site.toml")).unwrap();
+    fs::write(
+        src.join("site.toml"),
+        format!("ignore = [\"drafts/\", \"*.psd\"]\n{site_toml}"),
+    )
+    .unwrap();
+    fs::write(
+        src.join(".ssgignore"),
+        "node_modules/\n/README.md\n!.well-known/\n",
+    )
+    .unwrap();
+    fs::write(src.join("README.md"), "# About this repo").unwrap();
+    fs::create_dir_all(src.join("drafts")).unwrap();
+    fs::write(src.join("drafts/wip.md"), "---\ntitle: WIP\n---\n").unwrap();
+    fs::create_dir_all(src.join("node_modules/pkg")).unwrap();
+    fs::write(src.join("node_modules/pkg/index.js"), "x").unwrap();
+    fs::write(src.join("logo.psd"), "psd").unwrap();
+    fs::write(src.join("logo.png"), "png").unwrap();
+    fs::write(src.join(".DS_Store"), "").unwrap();
+    fs::create_dir_all(src.join(".git")).unwrap();
+    fs::write(src.join(".git/HEAD"), "ref").unwrap();
+    fs::create_dir_all(src.join(".well-known")).unwrap();
+    fs::write(src.join(".well-known/security.txt"), "Contact: x").unwrap();
+
+    build_site(&src, &out);
+
+    assert!(out.join("posts/first/index.html").exists());
+    assert!(out.join("logo.png").exists());
+    assert!(out.join(".well-known/security.txt").exists());
+    for gone in [
+        "README/index.html",
+        "drafts",
+        "node_modules",
+        "logo.psd",
+        ".DS_Store",
+        ".git",
+        ".ssgignore",
+    ] {
+        assert!(!out.join(gone).exists(), "{gone}");
+    }
+}
+
+#[test]
+fn removed_static_assets_disappear_from_the_output() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(src.join("img")).unwrap();
+    write_min_site(&src);
+    fs::write(src.join("img/a.png"), "a").unwrap();
+    fs::write(src.join("img/b.png"), "b").unwrap();
+
+    build_site(&src, &out);
+    assert_eq!(fs::read_to_string(out.join("img/a.png")).unwrap(), "a");
+    fs::write(out.join("extra.txt"), "mine").unwrap();
+
+    fs::remove_file(src.join("img/a.png")).unwrap();
+    fs::write(src.join("img/b.png"), "bb").unwrap();
+    build_site(&src, &out);
+    assert!(!out.join("img/a.png").exists());
+    assert_eq!(fs::read_to_string(out.join("img/b.png")).unwrap(), "bb");
+    assert_eq!(fs::read_to_string(src.join("img/b.png")).unwrap(), "bb");
+    // Files the build never wrote are left alone.
+    assert!(out.join("extra.txt").exists());
+    assert!(out.join("posts/first/index.html").exists());
+}
+
+#[test]
+fn listing_pages_rerender_when_a_listed_page_changes() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("templates/index.html"),
+        "{% for p in sections.posts.pages %}<li>{{ p.title }}</li>{% endfor %}",
+    )
+    .unwrap();
+    fs::write(
+        src.join("index.md"),
+        "---\ntitle: Home\ntemplate: index.html\n---\n",
+    )
+    .unwrap();
+
+    build_site(&src, &out);
+    let index = out.join("index.html");
+    assert!(
+        fs::read_to_string(&index)
+            .unwrap()
+            .contains("<li>First</li>")
+    );
+
+    fs::write(src.join("posts/first.md"), "---\ntitle: Renamed\n---\n").unwrap();
+    build_site(&src, &out);
+    assert!(
+        fs::read_to_string(&index)
+            .unwrap()
+            .contains("<li>Renamed</li>")
+    );
+}
+
+#[test]
+fn lookups_and_loaded_files_rerender_their_pages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let src = tmp.path().join("site");
+    let out = tmp.path().join("dist");
+    fs::create_dir_all(&src).unwrap();
+    write_min_site(&src);
+    fs::write(
+        src.join("templates/about.html"),
+        concat!(
+            r#"{% set stats = load_data(path="stats.json") %}"#,
+            r#"{% set first = get_page(path="posts/first.md") %}"#,
+            "{{ stats.visits }} {{ first.title }}",
+        ),
+    )
+    .unwrap();
+    fs::write(
+        src.join("about.md"),
+        "---\ntitle: About\ntemplate: about.html\n---\n",
+    )
+    .unwrap();
+    fs::write(src.join("stats.json"), r#"{"visits": 1}"#).unwrap();
+
+    build_site(&src, &out);
+    let about = out.join("about/index.html");
+    assert_eq!(fs::read_to_string(&about).unwrap(), "1 First");
+
+    fs::write(src.join("stats.json"), r#"{"visits": 2}"#).unwrap();
+    fs::write(src.join("posts/first.md"), "---\ntitle: Renamed\n---\n").unwrap();
+    build_site(&src, &out);
+    assert_eq!(fs::read_to_string(&about).unwrap(), "2 Renamed");
+}